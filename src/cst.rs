@@ -0,0 +1,247 @@
+// Lossless view of a smartconf file.
+//
+// `lex` does not keep whitespace, but every token knows its span in
+// the source, so a file is its tokens plus the text between them.
+// Edits replace the spans of some tokens and keep everything else:
+// comments, blank lines, quote style and key order.
+
+use crate::{Tok, TokValue};
+
+pub struct Cst {
+    pub source: String,
+    pub toks: Vec<Tok>,
+}
+
+// A `key: value` definition. `key` and `value` are ranges of indices
+// into `Cst::toks`; `value` includes a merge strategy like `!append`
+// but not a type annotation like `int =`.
+#[derive(Debug)]
+pub struct Entry {
+    pub path: Vec<String>,
+    pub key: ::std::ops::Range<usize>,
+    pub value: ::std::ops::Range<usize>,
+}
+
+fn is_specchar(tok: &Tok, specchar: &str) -> bool {
+    matches!(tok.value, TokValue::SPECCHAR { ref value } if value == specchar)
+}
+
+impl Cst {
+    pub fn new(source: String, toks: Vec<Tok>) -> Cst {
+        Cst {
+            source,
+            toks,
+        }
+    }
+
+    // Source text of the token at `index`
+    pub fn text(&self, index: usize) -> &str {
+        &self.source[self.toks[index].span.clone()]
+    }
+
+    // Source text between the token before `index` and the token at `index`
+    pub fn trivia(&self, index: usize) -> &str {
+        let start = match index {
+            0 => 0,
+            _ => self.toks[index - 1].span.end,
+        };
+        let end = match self.toks.get(index) {
+            Some(tok) => tok.span.start,
+            None => self.source.len(),
+        };
+        &self.source[start..end]
+    }
+
+    // Index of the first token at or after `index` that is not a comment
+    fn significant(&self, mut index: usize) -> usize {
+        while let Some(Tok { value: TokValue::ONE_LINE_COMMENT { .. }, .. }) = self.toks.get(index) {
+            index += 1;
+        }
+        index
+    }
+
+    // Index after the bracket that closes the one at `index`
+    fn skip_brackets(&self, mut index: usize) -> usize {
+        let mut depth = 0;
+        while index < self.toks.len() {
+            let tok = &self.toks[index];
+            if is_specchar(tok, "[") || is_specchar(tok, "{") {
+                depth += 1;
+            } else if is_specchar(tok, "]") || is_specchar(tok, "}") {
+                depth -= 1;
+                if depth == 0 {
+                    return index + 1;
+                }
+            }
+            index += 1;
+        }
+        index
+    }
+
+    // All definitions in the order they appear, with full paths
+    // (dotted keys and enclosing blocks resolved)
+    pub fn entries(&self) -> Vec<Entry> {
+        self.scan().0
+    }
+
+    // Definitions, and the full path and token index of each `unset`
+    fn scan(&self) -> (Vec<Entry>, Vec<(Vec<String>, usize)>) {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut unsets: Vec<(Vec<String>, usize)> = Vec::new();
+        // Path of each open block and the index of its entry
+        let mut blocks: Vec<(Vec<String>, usize)> = Vec::new();
+        let mut index = self.significant(0);
+        while index < self.toks.len() {
+            let tok = &self.toks[index];
+            let key = match tok.value {
+                TokValue::IDENTIFIER { ref value } if value == "include" => {
+                    let mut file_name = self.significant(index + 1);
+                    // `include FORMAT 'file'`
+                    if let Some(Tok { value: TokValue::IDENTIFIER { .. }, .. }) = self.toks.get(file_name) {
+                        file_name = self.significant(file_name + 1);
+                    }
+                    index = self.significant(file_name + 1);
+                    continue;
+                },
+                TokValue::IDENTIFIER { ref value } if value == "unset" => {
+                    let unset = index;
+                    let mut path = match blocks.last() {
+                        Some((path, _)) => path.clone(),
+                        None => Vec::new(),
+                    };
+                    index = self.significant(index + 1);
+                    while let Some(TokValue::IDENTIFIER { value }) | Some(TokValue::STRING { value, .. }) = self.toks.get(index).map(|tok| &tok.value) {
+                        path.push(value.to_string());
+                        index += 1;
+                        if index + 1 >= self.toks.len() || !is_specchar(&self.toks[index], ".") {
+                            break;
+                        }
+                        index += 1;
+                    }
+                    unsets.push((path, unset));
+                    index = self.significant(index);
+                    continue;
+                },
+                TokValue::IDENTIFIER { ref value } | TokValue::STRING { ref value, .. } => value.to_string(),
+                TokValue::SPECCHAR { ref value } if value == "}" => {
+                    if let Some((_, entry)) = blocks.pop() {
+                        entries[entry].value.end = index + 1;
+                    }
+                    index = self.significant(index + 1);
+                    continue;
+                },
+                _ => {
+                    index = self.significant(index + 1);
+                    continue;
+                },
+            };
+            let mut path = match blocks.last() {
+                Some((path, _)) => path.clone(),
+                None => Vec::new(),
+            };
+            path.push(key);
+            let key_start = index;
+            index = self.significant(index + 1);
+            while index + 1 < self.toks.len() && is_specchar(&self.toks[index], ".") {
+                match self.toks[index + 1].value {
+                    TokValue::IDENTIFIER { ref value } | TokValue::STRING { ref value, .. } => {
+                        path.push(value.to_string());
+                    },
+                    _ => break,
+                }
+                index = self.significant(index + 2);
+            }
+            let key = key_start..index;
+            if index >= self.toks.len() || !is_specchar(&self.toks[index], ":") {
+                continue;
+            }
+            let mut value_start = self.significant(index + 1);
+            if let Some(Tok { value: TokValue::IDENTIFIER { .. }, .. }) = self.toks.get(value_start) {
+                let equals = self.significant(value_start + 1);
+                if equals < self.toks.len() && is_specchar(&self.toks[equals], "=") {
+                    value_start = self.significant(equals + 1);
+                }
+            }
+            index = value_start;
+            if index < self.toks.len() && is_specchar(&self.toks[index], "!") {
+                index = self.significant(index + 2);
+                if index >= self.toks.len() || !(is_specchar(&self.toks[index], "{") || is_specchar(&self.toks[index], "[")) {
+                    // `!delete`
+                    entries.push(Entry { path, key, value: value_start..index });
+                    continue;
+                }
+            }
+            if index >= self.toks.len() {
+                break;
+            }
+            if is_specchar(&self.toks[index], "{") {
+                blocks.push((path.clone(), entries.len()));
+                entries.push(Entry { path, key, value: value_start..self.toks.len() });
+                index = self.significant(index + 1);
+                continue;
+            }
+            let value_end = if is_specchar(&self.toks[index], "[") {
+                self.skip_brackets(index)
+            } else {
+                index + 1
+            };
+            entries.push(Entry { path, key, value: value_start..value_end });
+            index = self.significant(value_end);
+        }
+        (entries, unsets)
+    }
+
+    // Whether `entry` is `key: !delete`
+    fn is_delete(&self, entry: &Entry) -> bool {
+        if !is_specchar(&self.toks[entry.value.start], "!") {
+            return false;
+        }
+        let strategy = self.significant(entry.value.start + 1);
+        matches!(self.toks.get(strategy), Some(Tok { value: TokValue::IDENTIFIER { ref value }, .. }) if value == "delete")
+    }
+
+    // Source with the value of `path` replaced by `value`, or with
+    // `path: value` appended when `path` is not defined here or is
+    // removed after its last definition
+    pub fn set(&self, path: &[String], value: &str) -> String {
+        let (entries, unsets) = self.scan();
+        // `unset` or `!delete` of `path` or a table above it after the
+        // token at `index`
+        let removed_after = |index: usize| {
+            unsets.iter().any(|(unset, at)| *at > index && path.starts_with(unset))
+                || entries.iter().any(|entry| entry.key.start > index && path.starts_with(&entry.path) && self.is_delete(entry))
+        };
+        let entry = entries.iter().rev()
+            .find(|entry| entry.path == path)
+            .filter(|entry| !removed_after(entry.key.start));
+        let quote_type = match entry {
+            Some(entry) if entry.value.len() == 1 => {
+                match self.toks[entry.value.start].value {
+                    TokValue::STRING { quote_type, .. } => Some(quote_type),
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+        let value = match (quote_type, crate::parse_parameter(value)) {
+            (Some(quote_type), _) => crate::smartconf_repr(value, quote_type),
+            (None, crate::Item::Text(text)) => crate::smartconf_repr(&text, '\''),
+            (None, _) => value.to_string(),
+        };
+        match entry {
+            Some(entry) => {
+                let start = self.toks[entry.value.start].span.start;
+                let end = self.toks[entry.value.end - 1].span.end;
+                format!("{}{}{}", &self.source[..start], value, &self.source[end..])
+            },
+            None => {
+                let mut result = self.source.clone();
+                if !result.is_empty() && !result.ends_with('\n') {
+                    result.push('\n');
+                }
+                result += &format!("{}: {}\n", crate::key_path_to_string(path), value);
+                result
+            },
+        }
+    }
+}
@@ -0,0 +1,250 @@
+// dotenv reader for `import`.
+//
+// Lines are `NAME=value`, optionally preceded by `export`; lines
+// starting with `#` are comments. Values in single quotes are taken as
+// they are, values in double quotes understand `\n`, `\t`, `\r`, `\"`,
+// `\\` and `\$`, and both can span lines. Unquoted values end at a
+// ` #` comment and are typed like INI values. Names are not split into
+// nested keys.
+
+use ::std::collections::HashMap;
+
+use crate::json::{Error, Locations};
+use crate::Item;
+
+type Table = HashMap<String, Item>;
+
+pub fn is_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {},
+        _ => return false,
+    }
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    line_number: usize,
+    column_number: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line_number += 1;
+            self.column_number = 1;
+        } else {
+            self.column_number += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, Error> {
+        Err((self.line_number, self.column_number, message.to_string()))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t') = self.peek() {
+            self.next();
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next() {
+            if c == '\n' {
+                return;
+            }
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            name.push(c);
+            self.next();
+        }
+        name
+    }
+
+    // After the opening quote
+    fn quoted(&mut self, quote: char) -> Result<String, Error> {
+        let mut result = String::new();
+        loop {
+            match self.next() {
+                None => return self.error("Unterminated string"),
+                Some(c) if c == quote => return Ok(result),
+                Some('\\') if quote == '"' => {
+                    match self.next() {
+                        Some('n') => result.push('\n'),
+                        Some('t') => result.push('\t'),
+                        Some('r') => result.push('\r'),
+                        Some(c @ ('"' | '\\' | '$')) => result.push(c),
+                        Some(c) => {
+                            result.push('\\');
+                            result.push(c);
+                        },
+                        None => return self.error("Unterminated string"),
+                    }
+                },
+                Some(c) => result.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Item, Error> {
+        match self.peek() {
+            Some(quote @ ('\'' | '"')) => {
+                self.next();
+                let value = self.quoted(quote)?;
+                self.skip_whitespace();
+                match self.peek() {
+                    None | Some('\n' | '\r' | '#') => {
+                        self.skip_line();
+                        Ok(Item::Text(value))
+                    },
+                    Some(c) => self.error(&format!("Unexpected `{}` after the value", c)),
+                }
+            },
+            _ => {
+                let mut value = String::new();
+                while let Some(c) = self.peek().filter(|c| *c != '\n') {
+                    if c == '#' && (value.is_empty() || value.ends_with([' ', '\t'])) {
+                        break;
+                    }
+                    value.push(c);
+                    self.next();
+                }
+                self.skip_line();
+                let value = value.trim();
+                Ok(match crate::parse_parameter(value) {
+                    item @ (Item::Number(_) | Item::Boolean(_)) if item.to_string() == value => item,
+                    _ => Item::Text(value.to_string()),
+                })
+            },
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<(Table, Locations), Error> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        index: 0,
+        line_number: 1,
+        column_number: 1,
+    };
+    let mut table = Table::new();
+    let mut locations = Locations::new();
+    loop {
+        while let Some(' ' | '\t' | '\r' | '\n') = parser.peek() {
+            parser.next();
+        }
+        match parser.peek() {
+            None => break,
+            Some('#') => {
+                parser.skip_line();
+                continue;
+            },
+            _ => {},
+        }
+        let (mut line_number, mut column_number) = (parser.line_number, parser.column_number);
+        let mut name = parser.name();
+        if name == "export" && matches!(parser.peek(), Some(' ' | '\t')) {
+            parser.skip_whitespace();
+            (line_number, column_number) = (parser.line_number, parser.column_number);
+            name = parser.name();
+        }
+        if !is_name(&name) {
+            return Err((line_number, column_number, String::from("Expected a variable name")));
+        }
+        parser.skip_whitespace();
+        if parser.peek() != Some('=') {
+            return parser.error("Expected `=`");
+        }
+        parser.next();
+        parser.skip_whitespace();
+        let location = (vec![name.clone()], parser.line_number, parser.column_number);
+        table.insert(name, parser.value()?);
+        locations.push(location);
+    }
+    Ok((table, locations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `text` read and written back as smartconf
+    fn read(text: &str) -> String {
+        let (table, _) = parse(text).unwrap();
+        crate::fmt::item(&Item::Table(table))
+    }
+
+    fn error(text: &str) -> Error {
+        parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn quoting() {
+        let text = "\
+PLAIN=a b c
+SINGLE='no \\n $escapes'
+DOUBLE=\"tab\\there \\\"q\\\" \\$HOME \\\\ \\x\"
+MULTI=\"one
+two\"
+EMPTY=
+EMPTY_QUOTED=''
+";
+        assert_eq!(read(text), "\
+DOUBLE: 'tab\\there \"q\" $HOME \\\\ \\\\x'
+EMPTY: ''
+EMPTY_QUOTED: ''
+MULTI: 'one\\ntwo'
+PLAIN: 'a b c'
+SINGLE: 'no \\\\n $escapes'
+");
+    }
+
+    #[test]
+    fn export_and_comments() {
+        let text = "\
+# comment
+export A=1
+  export\tB = true # note
+C=x#y
+D='q' # note
+exported=2
+export=3
+";
+        assert_eq!(read(text), "A: 1\nB: true\nC: 'x#y'\nD: 'q'\nexport: 3\nexported: 2\n");
+    }
+
+    #[test]
+    fn typing() {
+        assert_eq!(read("A=08\nB=1.50\nC=2.5\nD=False\n"), "A: '08'\nB: '1.50'\nC: 2.5\nD: 'False'\n");
+    }
+
+    #[test]
+    fn error_locations() {
+        assert_eq!(error("A=1\n1B=2\n"), (2, 1, String::from("Expected a variable name")));
+        assert_eq!(error("export  -A=1\n"), (1, 9, String::from("Expected a variable name")));
+        assert_eq!(error("A 1\n"), (1, 3, String::from("Expected `=`")));
+        assert_eq!(error("A='x' y\n"), (1, 7, String::from("Unexpected `y` after the value")));
+        assert_eq!(error("A=\"abc\n"), (2, 1, String::from("Unterminated string")));
+    }
+
+    #[test]
+    fn locations() {
+        let (_, locations) = parse("A=1\n\nexport B = 'x'\n").unwrap();
+        assert_eq!(locations, vec![
+            (vec![String::from("A")], 1, 3),
+            (vec![String::from("B")], 3, 12),
+        ]);
+    }
+}
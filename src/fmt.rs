@@ -0,0 +1,405 @@
+// Canonical layout of a smartconf file.
+//
+// One definition per line, `key: value` or `key: type = value` with a
+// single space after the colon, blocks indented by 4 spaces, strings in
+// single quotes (double quotes when that saves escaping), at most one
+// blank line in a row.
+// Comments stay where they are: a comment on the line of a definition
+// stays at the end of that line, a comment on its own line stays right
+// above the definition after it.
+
+use ::std::collections::HashMap;
+
+use crate::cst::Cst;
+use crate::{Item, Number, Tok, TokValue};
+
+const INDENT: &str = "    ";
+
+// Quote character `fmt` writes `s` with
+fn quote_type(s: &str) -> char {
+    if s.contains('\'') && !s.contains('"') {
+        '"'
+    } else {
+        '\''
+    }
+}
+
+fn is_specchar(tok: Option<&Tok>, specchar: &str) -> bool {
+    matches!(tok, Some(Tok { value: TokValue::SPECCHAR { ref value }, .. }) if value == specchar)
+}
+
+struct Formatter<'a> {
+    cst: &'a Cst,
+    // Index of the next token to print
+    index: usize,
+    out: String,
+    // The line being written and the depth it was started at
+    line: String,
+    line_depth: usize,
+    depth: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn peek(&self) -> Option<&'a Tok> {
+        self.cst.toks.get(self.index)
+    }
+
+    fn push(&mut self, text: &str) {
+        if self.line.is_empty() {
+            self.line_depth = self.depth;
+        }
+        self.line += text;
+    }
+
+    fn newline(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        for _ in 0..self.line_depth {
+            self.out += INDENT;
+        }
+        self.out += self.line.trim_end();
+        self.out.push('\n');
+        self.line.clear();
+    }
+
+    // Starts a line for the token at `index`, keeping one blank line
+    // before it if the source had any
+    fn start_line(&mut self) {
+        self.newline();
+        if self.index < self.cst.toks.len()
+            && self.cst.trivia(self.index).matches('\n').count() >= 2
+            && !self.out.is_empty()
+            && !self.out.ends_with("{\n")
+            && !self.out.ends_with("[\n")
+        {
+            self.out.push('\n');
+        }
+    }
+
+    fn comments(&mut self) {
+        while let Some(Tok { value: TokValue::ONE_LINE_COMMENT { value }, .. }) = self.peek() {
+            let comment = format!("#{}", value.trim_end());
+            if !self.line.is_empty() && !self.cst.trivia(self.index).contains('\n') {
+                self.line = format!("{}  {}", self.line.trim_end(), comment);
+                self.newline();
+            } else {
+                self.start_line();
+                self.push(&comment);
+            }
+            self.index += 1;
+        }
+    }
+
+    // Definitions up to the end of the file or the closing `}`
+    fn table(&mut self) -> Result<(), usize> {
+        loop {
+            self.comments();
+            let tok = match self.peek() {
+                Some(tok) => tok,
+                None => return Ok(()),
+            };
+            match tok.value {
+                TokValue::SPECCHAR { ref value } if value == "}" && self.depth > 0 => {
+                    return Ok(());
+                },
+                TokValue::IDENTIFIER { ref value } if value == "include" => {
+                    self.start_line();
+                    self.push("include ");
+                    self.index += 1;
+                    self.comments();
+                    if let Some(Tok { value: TokValue::IDENTIFIER { value }, .. }) = self.peek() {
+                        self.push(&format!("{} ", value));
+                        self.index += 1;
+                        self.comments();
+                    }
+                    match self.peek() {
+                        Some(Tok { value: TokValue::STRING { value, .. }, .. }) => {
+                            self.push(&crate::smartconf_repr(value, quote_type(value)));
+                            self.index += 1;
+                        },
+                        _ => return Err(self.index),
+                    }
+                },
+                TokValue::IDENTIFIER { ref value } if value == "unset" => {
+                    self.start_line();
+                    self.push("unset ");
+                    self.index += 1;
+                    self.key_path()?;
+                },
+                TokValue::IDENTIFIER { .. } | TokValue::STRING { .. } => {
+                    self.start_line();
+                    self.key_path()?;
+                    self.comments();
+                    if !is_specchar(self.peek(), ":") {
+                        return Err(self.index);
+                    }
+                    self.push(": ");
+                    self.index += 1;
+                    self.comments();
+                    if let Some(Tok { value: TokValue::IDENTIFIER { value }, .. }) = self.peek() {
+                        self.push(&format!("{} = ", value));
+                        self.index += 1;
+                        self.comments();
+                        if !is_specchar(self.peek(), "=") {
+                            return Err(self.index);
+                        }
+                        self.index += 1;
+                    }
+                    self.value()?;
+                },
+                _ => return Err(self.index),
+            }
+        }
+    }
+
+    fn key_path(&mut self) -> Result<(), usize> {
+        loop {
+            self.comments();
+            match self.peek() {
+                Some(Tok { value: TokValue::IDENTIFIER { value }, .. })
+                | Some(Tok { value: TokValue::STRING { value, .. }, .. }) => {
+                    self.push(&crate::key_to_string(value));
+                    self.index += 1;
+                },
+                _ => return Err(self.index),
+            }
+            let next = self.cst.toks.iter().skip(self.index).find(|tok| {
+                !matches!(tok.value, TokValue::ONE_LINE_COMMENT { .. })
+            });
+            if !is_specchar(next, ".") {
+                return Ok(());
+            }
+            self.comments();
+            self.push(".");
+            self.index += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<(), usize> {
+        self.comments();
+        let tok = match self.peek() {
+            Some(tok) => tok,
+            None => return Err(self.index),
+        };
+        match tok.value {
+            TokValue::STRING { ref value, .. } => {
+                self.push(&crate::smartconf_repr(value, quote_type(value)));
+                self.index += 1;
+            },
+            TokValue::NUMBER { .. } | TokValue::BOOLEAN { .. } | TokValue::REFERENCE { .. } => {
+                let text = self.cst.text(self.index).to_string();
+                self.push(&text);
+                self.index += 1;
+            },
+            TokValue::SPECCHAR { ref value } if value == "!" => {
+                self.push("!");
+                self.index += 1;
+                self.comments();
+                let strategy = match self.peek() {
+                    Some(Tok { value: TokValue::IDENTIFIER { value }, .. }) => value,
+                    _ => return Err(self.index),
+                };
+                self.push(strategy);
+                self.index += 1;
+                if strategy != "delete" {
+                    self.push(" ");
+                    self.value()?;
+                }
+            },
+            TokValue::SPECCHAR { ref value } if value == "{" => {
+                self.push("{");
+                self.index += 1;
+                if is_specchar(self.peek(), "}") {
+                    self.push("}");
+                    self.index += 1;
+                    return Ok(());
+                }
+                self.depth += 1;
+                self.table()?;
+                if !is_specchar(self.peek(), "}") {
+                    return Err(self.index);
+                }
+                self.newline();
+                self.depth -= 1;
+                self.push("}");
+                self.index += 1;
+            },
+            TokValue::SPECCHAR { ref value } if value == "[" => {
+                self.list()?;
+            },
+            _ => return Err(self.index),
+        }
+        Ok(())
+    }
+
+    // Short lists stay on one line, lists that were split over several
+    // lines or contain comments or tables get one item per line
+    fn list(&mut self) -> Result<(), usize> {
+        let start = self.index;
+        let mut end = start;
+        let mut depth = 0;
+        let mut inline = true;
+        while end < self.cst.toks.len() {
+            match self.cst.toks[end].value {
+                TokValue::SPECCHAR { ref value } if value == "[" => depth += 1,
+                TokValue::SPECCHAR { ref value } if value == "]" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                TokValue::SPECCHAR { ref value } if value == "{" => inline = false,
+                TokValue::ONE_LINE_COMMENT { .. } => inline = false,
+                _ => {},
+            }
+            end += 1;
+        }
+        if end >= self.cst.toks.len() {
+            return Err(end);
+        }
+        if self.cst.source[self.cst.toks[start].span.end..self.cst.toks[end].span.start].contains('\n') {
+            inline = false;
+        }
+        self.push("[");
+        self.index += 1;
+        if !inline {
+            self.depth += 1;
+        }
+        let mut first = true;
+        loop {
+            self.comments();
+            if is_specchar(self.peek(), "]") {
+                break;
+            }
+            if inline {
+                if !first {
+                    self.push(", ");
+                }
+            } else {
+                self.start_line();
+            }
+            first = false;
+            self.value()?;
+            let next = self.index + self.cst.toks[self.index..].iter().take_while(|tok| {
+                matches!(tok.value, TokValue::ONE_LINE_COMMENT { .. })
+            }).count();
+            if is_specchar(self.cst.toks.get(next), ",") {
+                if !inline {
+                    self.push(",");
+                }
+                self.comments();
+                self.index += 1;
+            } else if is_specchar(self.cst.toks.get(next), "]") {
+                if !inline {
+                    self.push(",");
+                }
+            } else {
+                return Err(next);
+            }
+        }
+        if !inline {
+            self.newline();
+            self.depth -= 1;
+        }
+        self.push("]");
+        self.index += 1;
+        Ok(())
+    }
+}
+
+// `cst` in canonical layout, or the index of the first token
+// that does not fit the grammar
+pub fn format(cst: &Cst) -> Result<String, usize> {
+    let mut formatter = Formatter {
+        cst,
+        index: 0,
+        out: String::new(),
+        line: String::new(),
+        line_depth: 0,
+        depth: 0,
+    };
+    formatter.table()?;
+    if formatter.index < cst.toks.len() {
+        return Err(formatter.index);
+    }
+    formatter.newline();
+    Ok(formatter.out)
+}
+
+// Definitions of `table` sorted by key, one per line
+fn definitions(table: &HashMap<String, Item>, depth: usize, out: &mut String) {
+    let mut keys: Vec<&String> = table.keys().collect();
+    keys.sort();
+    for key in keys {
+        *out += &INDENT.repeat(depth);
+        *out += &crate::key_to_string(key);
+        *out += ": ";
+        value(&table[key], depth, out);
+        out.push('\n');
+    }
+}
+
+// `item` written as a value at `depth`; lists of tables get one item
+// per line like in `format`
+fn value(item: &Item, depth: usize, out: &mut String) {
+    match item {
+        Item::Text(text) => {
+            // Strings are templates, keep `${` literal
+            let text = text.replace("${", "$${");
+            *out += &crate::smartconf_repr(&text, quote_type(&text));
+        },
+        // `{:?}` writes exponents the lexer does not read
+        Item::Number(Number::FLOAT { value }) => {
+            let text = value.to_string();
+            *out += &text;
+            if !text.contains('.') {
+                *out += ".0";
+            }
+        },
+        Item::Number(_) | Item::Boolean(_) => *out += &item.to_string(),
+        Item::Item(item) => value(item, depth, out),
+        Item::Table(table) if table.is_empty() => *out += "{}",
+        Item::Table(table) => {
+            *out += "{\n";
+            definitions(table, depth + 1, out);
+            *out += &INDENT.repeat(depth);
+            out.push('}');
+        },
+        Item::List(list) if list.iter().any(|item| matches!(item, Item::Table(table) if !table.is_empty())) => {
+            *out += "[\n";
+            for item in list {
+                *out += &INDENT.repeat(depth + 1);
+                value(item, depth + 1, out);
+                *out += ",\n";
+            }
+            *out += &INDENT.repeat(depth);
+            out.push(']');
+        },
+        Item::List(list) => {
+            out.push('[');
+            for (index, item) in list.iter().enumerate() {
+                if index > 0 {
+                    *out += ", ";
+                }
+                value(item, depth, out);
+            }
+            out.push(']');
+        },
+        Item::Reference { .. } | Item::Template { .. } => unreachable!(),
+    }
+}
+
+// Resolved `item` as smartconf source: a table as its definitions,
+// anything else as a single value
+pub fn item(item: &Item) -> String {
+    let mut out = String::new();
+    match item {
+        Item::Table(table) => definitions(table, 0, &mut out),
+        _ => {
+            value(item, 0, &mut out);
+            out.push('\n');
+        },
+    }
+    out
+}
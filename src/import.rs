@@ -0,0 +1,141 @@
+// Files in other formats, read as smartconf input.
+//
+// An imported file is a layer like a smartconf file: its keys are
+// merged into what was defined before it, tables key by key, and its
+// values keep their place in the provenance.
+
+use ::std::collections::HashMap;
+
+use crate::json::{Error, Json, Locations};
+use crate::{Config, InputFormat, Item, Loc, MergeStrategy, Number, Provenance};
+
+type Table = HashMap<String, Item>;
+
+fn json_number(text: &str) -> Result<Number, String> {
+    if text.starts_with('-') {
+        return Err(format!("Negative numbers are not supported: {}", text));
+    }
+    if text.chars().all(|c| c.is_ascii_digit()) {
+        return match text.parse() {
+            Ok(value) => Ok(Number::USIZE { value }),
+            Err(_) => Err(format!("Number is too large: {}", text)),
+        };
+    }
+    match text.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(Number::FLOAT { value }),
+        Ok(_) => Err(format!("Number is too large: {}", text)),
+        Err(_) => Err(format!("Invalid number: {}", text)),
+    }
+}
+
+// `json`, which is at `path` in the file, as an item; errors say
+// where by key path
+fn json_item(json: &Json, path: &mut Vec<String>) -> Result<Item, (Vec<String>, String)> {
+    match json {
+        Json::Null => Err((path.clone(), String::from("`null` has no smartconf equivalent"))),
+        Json::Boolean(value) => Ok(Item::Boolean(*value)),
+        Json::Number(text) => json_number(text).map(Item::Number).map_err(|e| (path.clone(), e)),
+        Json::String(text) => Ok(Item::Text(text.clone())),
+        Json::Array(items) => {
+            let items: Result<Vec<Item>, _> = items.iter().map(|item| json_item(item, path)).collect();
+            Ok(Item::List(items?))
+        },
+        Json::Object(members) => {
+            let mut table = Table::new();
+            for (key, value) in members {
+                path.push(key.clone());
+                let item = json_item(value, path)?;
+                path.pop();
+                table.insert(key.clone(), item);
+            }
+            Ok(Item::Table(table))
+        },
+    }
+}
+
+fn json_table(source: &str) -> Result<(Table, Locations), Error> {
+    let (json, locations) = crate::json::parse_located(source)?;
+    match json_item(&json, &mut Vec::new()) {
+        Ok(Item::Table(table)) => Ok((table, locations)),
+        Ok(_) => Err((1, 1, String::from("Expected an object"))),
+        Err((path, message)) => {
+            // The value itself, or the closest member it is in
+            let (_, line_number, column_number) = locations.iter()
+                .filter(|(located, _, _)| path.starts_with(located))
+                .max_by_key(|(located, _, _)| located.len())
+                .cloned()
+                .unwrap_or((Vec::new(), 1, 1));
+            Err((line_number, column_number, message))
+        },
+    }
+}
+
+// Merges the file of `config`, written in `format`, into `base`, which
+// is at `prefix` in the result
+pub fn layer(config: &Config, format: InputFormat, base: Table, prefix: &[String], provenance: &mut Provenance) -> Table {
+    let file_name = config.input_file_name.clone().unwrap();
+    let display_file_name = crate::display_file_name(config, &file_name);
+    macro_rules! import_error {
+        ($line_number: expr, $column_number: expr, $($msg: expr),+) => {
+            error!("import: {}: {}: {}: {}", display_file_name, $line_number + config.starting_line_number - 1, $column_number + config.starting_column_number - 1, format!($($msg,)+));
+        };
+    }
+    let source = match crate::read_input(&file_name) {
+        Ok(source) => source,
+        Err(e) => {
+            error!("import: Cannot read file: {}: {}", display_file_name, e);
+            ::std::process::exit(2);
+        },
+    };
+    let parsed = match format {
+        InputFormat::JSON => json_table(&source),
+        InputFormat::TOML => crate::toml::parse(&source),
+        InputFormat::INI => crate::ini::parse(&source),
+        InputFormat::Dotenv => crate::dotenv::parse(&source),
+        InputFormat::Smartconf => unreachable!(),
+    };
+    let (table, locations) = match parsed {
+        Ok(parsed) => parsed,
+        Err((line_number, column_number, message)) => {
+            import_error!(line_number, column_number, "{}", message);
+            ::std::process::exit(3);
+        },
+    };
+    for (path, line_number, column_number) in locations {
+        let mut full_path = prefix.to_vec();
+        full_path.extend(path.iter().cloned());
+        if !matches!(crate::get_path(&table, &path), Some(Item::Table(_))) {
+            provenance.forget(&full_path);
+        }
+        provenance.keys.insert(full_path, Loc {
+            filename: display_file_name.clone(),
+            line_number: line_number + config.starting_line_number - 1,
+            column_number: column_number + config.starting_column_number - 1,
+        });
+    }
+    match crate::merge_items(Item::Table(base), Item::Table(table), MergeStrategy::Merge) {
+        Item::Table(table) => table,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_values_without_equivalent() {
+        let error = |source: &str| json_table(source).unwrap_err();
+        assert_eq!(error("{\n  \"a\": {\"b\": null}\n}"), (2, 14, String::from("`null` has no smartconf equivalent")));
+        assert_eq!(error("{\"a\": [1, -2]}"), (1, 7, String::from("Negative numbers are not supported: -2")));
+        assert_eq!(error("{\"a\": 99999999999999999999999}"), (1, 7, String::from("Number is too large: 99999999999999999999999")));
+        assert_eq!(error("[1]"), (1, 1, String::from("Expected an object")));
+    }
+
+    #[test]
+    fn json_numbers() {
+        let (table, _) = json_table("{\"i\": 3, \"f\": 2.5e1}").unwrap();
+        assert_eq!(table["i"].to_string(), "3");
+        assert_eq!(table["f"].to_string(), "25.0");
+    }
+}
@@ -0,0 +1,205 @@
+// INI reader for `import`.
+//
+// `[section]` starts a table; dots in its name nest tables, and so does
+// a git-style `[section "subsection"]`. Keys are set with `key = value`
+// or `key: value`, and lines starting with `;` or `#` are comments.
+// Unquoted values are typed like `-D` values, as long as that does not
+// change how they are written (`08` stays a string).
+
+use ::std::collections::HashMap;
+
+use crate::json::{Error, Locations};
+use crate::Item;
+
+type Table = HashMap<String, Item>;
+
+fn unquote(text: &str) -> Option<&str> {
+    for quote in ['"', '\''] {
+        if text.len() >= 2 && text.starts_with(quote) && text.ends_with(quote) {
+            return Some(&text[1..text.len() - 1]);
+        }
+    }
+    None
+}
+
+// Key path of the section `name`
+fn section_path(name: &str) -> Option<Vec<String>> {
+    let name = name.trim();
+    if let Some((section, subsection)) = name.split_once(char::is_whitespace) {
+        let subsection = unquote(subsection.trim())?;
+        return Some(vec![section.to_string(), subsection.to_string()]);
+    }
+    let path: Vec<String> = name.split('.').map(|key| key.trim().to_string()).collect();
+    if path.iter().any(|key| key.is_empty()) {
+        return None;
+    }
+    Some(path)
+}
+
+fn value_item(text: &str) -> Item {
+    if let Some(text) = unquote(text) {
+        return Item::Text(text.to_string());
+    }
+    match crate::parse_parameter(text) {
+        item @ (Item::Number(_) | Item::Boolean(_)) if item.to_string() == text => item,
+        _ => Item::Text(text.to_string()),
+    }
+}
+
+// Table at `path` in `table`, creating missing tables
+fn walk<'a>(mut table: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    for (index, key) in path.iter().enumerate() {
+        table = match table.entry(key.clone()).or_insert_with(|| Item::Table(Table::new())) {
+            Item::Table(table) => table,
+            _ => return Err(format!("`{}` is not a section", path[..=index].join("."))),
+        };
+    }
+    Ok(table)
+}
+
+pub fn parse(text: &str) -> Result<(Table, Locations), Error> {
+    let mut root = Table::new();
+    let mut locations = Locations::new();
+    let mut section: Vec<String> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let indent = line.len() - line.trim_start().len();
+        let trimmed = line.trim();
+        // Column of byte `offset` of `line`
+        let column_number = |offset: usize| line[..offset].chars().count() + 1;
+        if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = trimmed.strip_prefix('[') {
+            let path = match name.strip_suffix(']').and_then(section_path) {
+                Some(path) => path,
+                None => return Err((line_number, column_number(indent), String::from("Invalid section header"))),
+            };
+            if let Err(message) = walk(&mut root, &path) {
+                return Err((line_number, column_number(indent), message));
+            }
+            locations.push((path.clone(), line_number, column_number(indent)));
+            section = path;
+            continue;
+        }
+        let (key, value, value_offset) = match trimmed.find(['=', ':']) {
+            Some(separator) => {
+                let value = &trimmed[separator + 1..];
+                let offset = indent + separator + 1 + value.len() - value.trim_start().len();
+                (trimmed[..separator].trim(), value.trim(), offset)
+            },
+            None => return Err((line_number, column_number(indent), String::from("Expected `=`"))),
+        };
+        if key.is_empty() {
+            return Err((line_number, column_number(indent), String::from("Expected a key")));
+        }
+        // A comment after an unquoted value
+        let value = match unquote(value) {
+            Some(_) => value,
+            None => match value.find(" ;").or_else(|| value.find(" #")) {
+                Some(comment) => value[..comment].trim_end(),
+                None => value,
+            },
+        };
+        let column = column_number(value_offset);
+        let table = match walk(&mut root, &section) {
+            Ok(table) => table,
+            Err(message) => return Err((line_number, column, message)),
+        };
+        table.insert(key.to_string(), value_item(value));
+        let mut path = section.clone();
+        path.push(key.to_string());
+        locations.push((path, line_number, column));
+    }
+    Ok((root, locations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `text` read and written back as smartconf
+    fn read(text: &str) -> String {
+        let (table, _) = parse(text).unwrap();
+        crate::fmt::item(&Item::Table(table))
+    }
+
+    fn error(text: &str) -> Error {
+        parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn sections() {
+        let text = "\
+top = 1
+[server]
+host = example.com
+[server.tls]
+port: 443
+[remote \"origin\"]
+url = 'git@host:repo'
+[server]
+name = main
+";
+        assert_eq!(read(text), "\
+remote: {
+    origin: {
+        url: 'git@host:repo'
+    }
+}
+server: {
+    host: 'example.com'
+    name: 'main'
+    tls: {
+        port: 443
+    }
+}
+top: 1
+");
+        assert_eq!(read("[empty]\n"), "empty: {}\n");
+    }
+
+    #[test]
+    fn values() {
+        let text = "\
+; comment
+# comment
+a = 08
+b = true
+c = 1.5
+d = x ; note
+e = \"quoted ; kept\"
+f =
+g = a=b
+";
+        assert_eq!(read(text), "\
+a: '08'
+b: true
+c: 1.5
+d: 'x'
+e: 'quoted ; kept'
+f: ''
+g: 'a=b'
+");
+    }
+
+    #[test]
+    fn error_locations() {
+        assert_eq!(error("[a\n"), (1, 1, String::from("Invalid section header")));
+        assert_eq!(error("  [a..b]\n"), (1, 3, String::from("Invalid section header")));
+        assert_eq!(error("[a 'b]\n"), (1, 1, String::from("Invalid section header")));
+        assert_eq!(error("a = 1\n  b\n"), (2, 3, String::from("Expected `=`")));
+        assert_eq!(error(" = 1\n"), (1, 2, String::from("Expected a key")));
+        assert_eq!(error("a = 1\n[a.b]\n"), (2, 1, String::from("`a` is not a section")));
+    }
+
+    #[test]
+    fn locations() {
+        let (_, locations) = parse("a = 1\n[s.t]\n  b:   'x'\n").unwrap();
+        assert_eq!(locations, vec![
+            (vec![String::from("a")], 1, 5),
+            (vec![String::from("s"), String::from("t")], 2, 1),
+            (vec![String::from("s"), String::from("t"), String::from("b")], 3, 8),
+        ]);
+    }
+}
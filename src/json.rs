@@ -0,0 +1,514 @@
+// Minimal JSON reader and writer.
+//
+// Objects keep their keys in order, numbers keep their text so that
+// integers of any size survive a round trip.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Boolean(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(value) => value.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+// Quotes and escapes `s` as a JSON string
+pub fn quote(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\t' => result += "\\t",
+            '\r' => result += "\\r",
+            c if (c as u32) < 0x20 => result += &format!("\\u{:04x}", c as u32),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+impl ::std::fmt::Display for Json {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Boolean(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write!(f, "{}", quote(value)),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in members.iter().enumerate() {
+                    if index != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", quote(name), value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+impl Json {
+    // Indented by 4 spaces per level, like `--format json`
+    pub fn pretty(&self, depth: usize) -> String {
+        let indent = "    ".repeat(depth + 1);
+        let closing = "    ".repeat(depth);
+        match self {
+            Json::Array(items) if !items.is_empty() => {
+                let items: Vec<String> = items.iter().map(|item| {
+                    format!("{}{}", indent, item.pretty(depth + 1))
+                }).collect();
+                format!("[\n{}\n{}]", items.join(",\n"), closing)
+            },
+            Json::Object(members) if !members.is_empty() => {
+                let members: Vec<String> = members.iter().map(|(name, value)| {
+                    format!("{}{}: {}", indent, quote(name), value.pretty(depth + 1))
+                }).collect();
+                format!("{{\n{}\n{}}}", members.join(",\n"), closing)
+            },
+            _ => self.to_string(),
+        }
+    }
+}
+
+// Builds a `Json::Object` from `key => value` pairs
+macro_rules! json_object {
+    ($($key: expr => $value: expr),* $(,)?) => {
+        crate::json::Json::Object(vec![$((String::from($key), $value)),*])
+    };
+}
+pub(crate) use json_object;
+
+// Line and column numbers of an error, and what is wrong
+pub type Error = (usize, usize, String);
+
+// Arrays and objects are read recursively, so their nesting is limited
+const MAX_DEPTH: usize = 512;
+
+struct Parser<'a> {
+    chars: ::std::iter::Peekable<::std::str::CharIndices<'a>>,
+    text: &'a str,
+    // Keys of the objects the parser is in, and how many of them
+    // are inside an array
+    path: Vec<String>,
+    arrays: usize,
+    // Path and byte offset of each member value outside arrays
+    locations: Vec<(Vec<String>, usize)>,
+    // Arrays and objects the parser is in
+    depth: usize,
+}
+
+// Whether `text` is a number in the JSON grammar: no leading zeros,
+// and digits after `.` and after the exponent
+fn is_number(text: &str) -> bool {
+    let bytes = text.strip_prefix('-').unwrap_or(text).as_bytes();
+    let digits = |index: usize| bytes[index..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+    let mut index = match bytes.first() {
+        Some(b'0') => 1,
+        Some(b'1'..=b'9') => digits(0),
+        _ => return false,
+    };
+    if bytes.get(index) == Some(&b'.') {
+        match digits(index + 1) {
+            0 => return false,
+            count => index += 1 + count,
+        }
+    }
+    if let Some(b'e' | b'E') = bytes.get(index) {
+        index += 1;
+        if let Some(b'+' | b'-') = bytes.get(index) {
+            index += 1;
+        }
+        match digits(index) {
+            0 => return false,
+            count => index += count,
+        }
+    }
+    index == bytes.len()
+}
+
+// Line and column numbers of byte `offset` of `text`
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let line_number = text[..offset].matches('\n').count() + 1;
+    let column_number = text[..offset].chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line_number, column_number)
+}
+
+impl<'a> Parser<'a> {
+    fn offset(&mut self) -> usize {
+        match self.chars.peek() {
+            Some((offset, _)) => *offset,
+            None => self.text.len(),
+        }
+    }
+
+    fn error<T>(&mut self, message: &str) -> Result<T, Error> {
+        let offset = self.offset();
+        self.error_at(offset, message)
+    }
+
+    fn error_at<T>(&self, offset: usize, message: &str) -> Result<T, Error> {
+        let (line_number, column_number) = line_column(self.text, offset);
+        Err((line_number, column_number, message.to_string()))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some((_, ' ' | '\t' | '\n' | '\r')) = self.chars.peek() {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, word: &str, value: Json) -> Result<Json, Error> {
+        let offset = self.offset();
+        for expected in word.chars() {
+            match self.chars.next() {
+                Some((_, c)) if c == expected => {},
+                _ => return self.error_at(offset, &format!("Expected `{}`", word)),
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        self.skip_whitespace();
+        let c = match self.chars.peek() {
+            Some((_, c)) => *c,
+            None => return self.error("Unexpected end of input"),
+        };
+        match c {
+            'n' => self.expect("null", Json::Null),
+            't' => self.expect("true", Json::Boolean(true)),
+            'f' => self.expect("false", Json::Boolean(false)),
+            '"' => Ok(Json::String(self.string()?)),
+            '[' | '{' => {
+                if self.depth == MAX_DEPTH {
+                    return self.error("Nesting too deep");
+                }
+                self.depth += 1;
+                let value = match c {
+                    '[' => self.array(),
+                    _ => self.object(),
+                };
+                self.depth -= 1;
+                value
+            },
+            '-' | '0'..='9' => {
+                let start = self.offset();
+                while let Some((_, '-' | '+' | '.' | 'e' | 'E' | '0'..='9')) = self.chars.peek() {
+                    self.chars.next();
+                }
+                let end = self.offset();
+                let number = &self.text[start..end];
+                if !is_number(number) {
+                    return self.error(&format!("Invalid number: `{}`", number));
+                }
+                Ok(Json::Number(number.to_string()))
+            },
+            _ => self.error(&format!("Unexpected character: `{}`", c)),
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, Error> {
+        self.chars.next();
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if let Some((_, ']')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(Json::Array(items));
+        }
+        self.arrays += 1;
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some((_, ',')) => {},
+                Some((_, ']')) => {
+                    self.chars.next();
+                    break;
+                },
+                _ => return self.error("Expected `,` or `]`"),
+            }
+            self.chars.next();
+        }
+        self.arrays -= 1;
+        Ok(Json::Array(items))
+    }
+
+    fn object(&mut self) -> Result<Json, Error> {
+        self.chars.next();
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if let Some((_, '}')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if !matches!(self.chars.peek(), Some((_, '"'))) {
+                return self.error("Expected string");
+            }
+            let name = self.string()?;
+            self.skip_whitespace();
+            if !matches!(self.chars.peek(), Some((_, ':'))) {
+                return self.error("Expected `:`");
+            }
+            self.chars.next();
+            self.skip_whitespace();
+            self.path.push(name.clone());
+            if self.arrays == 0 {
+                let offset = self.offset();
+                self.locations.push((self.path.clone(), offset));
+            }
+            members.push((name, self.value()?));
+            self.path.pop();
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some((_, ',')) => {},
+                Some((_, '}')) => {
+                    self.chars.next();
+                    return Ok(Json::Object(members));
+                },
+                _ => return self.error("Expected `,` or `}`"),
+            }
+            self.chars.next();
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.chars.next();
+        let mut result = String::new();
+        loop {
+            // Escape errors point at their `\`
+            let offset = self.offset();
+            let c = match self.chars.next() {
+                Some((_, c)) => c,
+                None => return self.error("Unterminated string"),
+            };
+            match c {
+                '"' => return Ok(result),
+                '\\' => {
+                    let c = match self.chars.next() {
+                        Some((_, c)) => c,
+                        None => return self.error("Unterminated string"),
+                    };
+                    match c {
+                        '"' => result.push('"'),
+                        '\\' => result.push('\\'),
+                        '/' => result.push('/'),
+                        'b' => result.push('\x08'),
+                        'f' => result.push('\x0C'),
+                        'n' => result.push('\n'),
+                        'r' => result.push('\r'),
+                        't' => result.push('\t'),
+                        'u' => {
+                            let mut code = self.hex4(offset)?;
+                            // A surrogate pair is two escapes
+                            if (0xD800..0xDC00).contains(&code) {
+                                let low_offset = self.offset();
+                                if !matches!(self.chars.next(), Some((_, '\\'))) || !matches!(self.chars.next(), Some((_, 'u'))) {
+                                    return self.error_at(low_offset, "Expected low surrogate");
+                                }
+                                let low = self.hex4(low_offset)?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return self.error_at(low_offset, "Expected low surrogate");
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            } else if (0xDC00..0xE000).contains(&code) {
+                                return self.error_at(offset, "Unexpected low surrogate");
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => result.push(c),
+                                None => return self.error_at(offset, "Invalid escape"),
+                            }
+                        },
+                        _ => return self.error_at(offset, "Invalid escape"),
+                    }
+                },
+                c => result.push(c),
+            }
+        }
+    }
+
+    // Four hex digits of the `\u` escape at `offset`
+    fn hex4(&mut self, offset: usize) -> Result<u32, Error> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = match self.chars.next() {
+                Some((_, c)) => c.to_digit(16),
+                None => None,
+            };
+            match digit {
+                Some(digit) => code = code * 16 + digit,
+                None => return self.error_at(offset, "Invalid escape"),
+            }
+        }
+        Ok(code)
+    }
+}
+
+// Parses `text`; errors are `LINE: COLUMN: MESSAGE`
+pub fn parse(text: &str) -> Result<Json, String> {
+    match parse_located(text) {
+        Ok((value, _)) => Ok(value),
+        Err((line_number, column_number, message)) => Err(format!("{}: {}: {}", line_number, column_number, message)),
+    }
+}
+
+// Key path, line and column numbers of the value of every object
+// member that is not inside an array
+pub type Locations = Vec<(Vec<String>, usize, usize)>;
+
+// Like `parse`, with the `Locations` of the values
+pub fn parse_located(text: &str) -> Result<(Json, Locations), Error> {
+    let mut parser = Parser {
+        chars: text.char_indices().peekable(),
+        text,
+        path: Vec::new(),
+        arrays: 0,
+        locations: Vec::new(),
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.chars.peek().is_some() {
+        return parser.error("Unexpected text after the value");
+    }
+    let locations = parser.locations.into_iter().map(|(path, offset)| {
+        let (line_number, column_number) = line_column(text, offset);
+        (path, line_number, column_number)
+    }).collect();
+    Ok((value, locations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `\u` escapes of `codes`
+    fn escapes(codes: &[&str]) -> String {
+        codes.iter().map(|code| format!("\\u{}", code)).collect()
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        let string = |codes: &[&str], rest: &str| parse(&format!("\"{}{}\"", escapes(codes), rest));
+        assert_eq!(string(&["d83d", "de00"], ""), Ok(Json::String(String::from("\u{1F600}"))));
+        assert_eq!(string(&["d800", "0041"], ""), Err(String::from("1: 8: Expected low surrogate")));
+        assert_eq!(string(&["d800", "d800"], ""), Err(String::from("1: 8: Expected low surrogate")));
+        assert_eq!(string(&["d800"], "x"), Err(String::from("1: 8: Expected low surrogate")));
+        assert_eq!(string(&["dc00"], ""), Err(String::from("1: 2: Unexpected low surrogate")));
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse(" null "), Ok(Json::Null));
+        assert_eq!(parse("[true, false]"), Ok(Json::Array(vec![Json::Boolean(true), Json::Boolean(false)])));
+        // Numbers keep their text
+        assert_eq!(parse("1.50e3"), Ok(Json::Number(String::from("1.50e3"))));
+        assert_eq!(parse("123456789012345678901234567890"), Ok(Json::Number(String::from("123456789012345678901234567890"))));
+        assert_eq!(parse(r#""a\"\\\/\n\t""#), Ok(Json::String(String::from("a\"\\/\n\t"))));
+        assert_eq!(parse(&format!("\"{}\"", escapes(&["00e9"]))), Ok(Json::String(String::from("é"))));
+        // Members keep their order
+        assert_eq!(parse(r#"{"b": 1, "a": {}}"#), Ok(Json::Object(vec![
+            (String::from("b"), Json::Number(String::from("1"))),
+            (String::from("a"), Json::Object(Vec::new())),
+        ])));
+    }
+
+    #[test]
+    fn error_locations() {
+        assert_eq!(parse(""), Err(String::from("1: 1: Unexpected end of input")));
+        assert_eq!(parse("[1 2]"), Err(String::from("1: 4: Expected `,` or `]`")));
+        assert_eq!(parse("[1,]"), Err(String::from("1: 4: Unexpected character: `]`")));
+        assert_eq!(parse("{\n  \"a\": 1,\n}"), Err(String::from("3: 1: Expected string")));
+        assert_eq!(parse("{\"a\" 1}"), Err(String::from("1: 6: Expected `:`")));
+        assert_eq!(parse("{\"a\": 1 \"b\": 2}"), Err(String::from("1: 9: Expected `,` or `}`")));
+        assert_eq!(parse("[nul]"), Err(String::from("1: 2: Expected `null`")));
+        assert_eq!(parse("\"abc"), Err(String::from("1: 5: Unterminated string")));
+        assert_eq!(parse("\"a\\x\""), Err(String::from("1: 3: Invalid escape")));
+        assert_eq!(parse("\"a\\u12g4\""), Err(String::from("1: 3: Invalid escape")));
+        assert_eq!(parse("1.2.3"), Err(String::from("1: 6: Invalid number: `1.2.3`")));
+        assert_eq!(parse("{} {}"), Err(String::from("1: 4: Unexpected text after the value")));
+        assert_eq!(parse("\u{FEFF}1"), Err(String::from("1: 1: Unexpected character: `\u{FEFF}`")));
+    }
+
+    #[test]
+    fn numbers() {
+        for number in ["0", "-0", "10", "0.5", "-1.25", "1e5", "1E+5", "2.5e-3", "0e0"] {
+            assert_eq!(parse(number), Ok(Json::Number(String::from(number))));
+        }
+        assert_eq!(parse("01"), Err(String::from("1: 3: Invalid number: `01`")));
+        assert_eq!(parse("-01"), Err(String::from("1: 4: Invalid number: `-01`")));
+        assert_eq!(parse("[1.]"), Err(String::from("1: 4: Invalid number: `1.`")));
+        assert_eq!(parse("1e"), Err(String::from("1: 3: Invalid number: `1e`")));
+        assert_eq!(parse("1e+"), Err(String::from("1: 4: Invalid number: `1e+`")));
+        assert_eq!(parse("-"), Err(String::from("1: 2: Invalid number: `-`")));
+        assert_eq!(parse("-.5"), Err(String::from("1: 4: Invalid number: `-.5`")));
+        assert_eq!(parse("+1"), Err(String::from("1: 1: Unexpected character: `+`")));
+    }
+
+    #[test]
+    fn nesting() {
+        let arrays = |depth: usize| format!("{}1{}", "[".repeat(depth), "]".repeat(depth));
+        let objects = |depth: usize| format!("{}1{}", "{\"a\": ".repeat(depth), "}".repeat(depth));
+        assert!(parse(&arrays(MAX_DEPTH)).is_ok());
+        assert!(parse(&objects(MAX_DEPTH)).is_ok());
+        assert_eq!(parse(&arrays(MAX_DEPTH + 1)), Err(format!("1: {}: Nesting too deep", MAX_DEPTH + 1)));
+        assert_eq!(parse(&objects(MAX_DEPTH + 1)), Err(format!("1: {}: Nesting too deep", MAX_DEPTH * 6 + 1)));
+        assert!(parse(&"[".repeat(1_000_000)).is_err());
+    }
+
+    #[test]
+    fn locations() {
+        let (_, locations) = parse_located("{\n  \"a\": {\"b\": 1},\n  \"c\": [{\"d\": 2}]\n}").unwrap();
+        assert_eq!(locations, vec![
+            (vec![String::from("a")], 2, 8),
+            (vec![String::from("a"), String::from("b")], 2, 14),
+            (vec![String::from("c")], 3, 8),
+        ]);
+    }
+}
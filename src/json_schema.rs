@@ -0,0 +1,168 @@
+// JSON Schema (draft 2020-12) for the JSON output of a config,
+// converted from a smartconf schema or inferred from a config.
+
+use ::std::collections::HashMap;
+
+use crate::json::{json_object, Json};
+use crate::schema::{Schema, Type};
+use crate::{Item, Number};
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+fn string(s: &str) -> Json {
+    Json::String(s.to_string())
+}
+
+fn number(n: f64) -> Json {
+    Json::Number(n.to_string())
+}
+
+fn item_to_json(item: &Item) -> Json {
+    match item {
+        Item::Text(text) => Json::String(text.clone()),
+        Item::Number(number) => Json::Number(number.to_string()),
+        Item::Boolean(value) => Json::Boolean(*value),
+        Item::Item(item) => item_to_json(item),
+        Item::Table(table) => {
+            let mut keys: Vec<&String> = table.keys().collect();
+            keys.sort();
+            Json::Object(keys.into_iter().map(|key| (key.clone(), item_to_json(&table[key]))).collect())
+        },
+        Item::List(list) => Json::Array(list.iter().map(item_to_json).collect()),
+        item @ (Item::Reference { .. } | Item::Template { .. }) => Json::String(item.to_string()),
+    }
+}
+
+fn type_name(kind: Type) -> Option<&'static str> {
+    match kind {
+        Type::String => Some("string"),
+        Type::Int => Some("integer"),
+        Type::Float => Some("number"),
+        Type::Number => Some("number"),
+        Type::Bool => Some("boolean"),
+        Type::Table => Some("object"),
+        Type::List => Some("array"),
+        Type::Any => None,
+    }
+}
+
+fn convert(schema: &Schema) -> Vec<(String, Json)> {
+    let mut result = Vec::new();
+    if let Some(description) = &schema.description {
+        result.push((String::from("description"), string(description)));
+    }
+    if let Some(name) = type_name(schema.kind) {
+        result.push((String::from("type"), string(name)));
+    }
+    if let Some(values) = &schema.enumeration {
+        result.push((String::from("enum"), Json::Array(values.iter().map(item_to_json).collect())));
+    }
+    if let Some(min) = schema.min {
+        result.push((String::from("minimum"), number(min)));
+    }
+    if let Some(max) = schema.max {
+        result.push((String::from("maximum"), number(max)));
+    }
+    if let Some((pattern, _)) = &schema.pattern {
+        result.push((String::from("pattern"), string(pattern)));
+    }
+    if !schema.keys.is_empty() {
+        let properties = schema.keys.iter().map(|(key, schema)| (key.clone(), Json::Object(convert(schema)))).collect();
+        result.push((String::from("properties"), Json::Object(properties)));
+        let required: Vec<Json> = schema.keys.iter().filter(|(_, schema)| schema.required).map(|(key, _)| string(key)).collect();
+        if !required.is_empty() {
+            result.push((String::from("required"), Json::Array(required)));
+        }
+        if !schema.additional {
+            result.push((String::from("additionalProperties"), Json::Boolean(false)));
+        }
+    }
+    if let Some(items) = &schema.items {
+        result.push((String::from("items"), Json::Object(convert(items))));
+    }
+    if let Some(default) = &schema.default {
+        result.push((String::from("default"), item_to_json(default)));
+    }
+    result
+}
+
+// JSON Schema of a smartconf schema
+pub fn from_schema(schema: &Schema) -> Json {
+    let mut result = vec![(String::from("$schema"), string(DIALECT))];
+    result.extend(convert(schema));
+    Json::Object(result)
+}
+
+// Schema of the tables in `tables`: each key has the schema of its
+// first value, and is required if all the tables have it
+fn infer_tables(tables: &[&HashMap<String, Item>]) -> Vec<(String, Json)> {
+    let mut keys: Vec<&String> = tables.iter().flat_map(|table| table.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let properties = keys.iter().map(|key| {
+        let values: Vec<&Item> = tables.iter().filter_map(|table| table.get(*key)).collect();
+        ((*key).clone(), infer_items(&values))
+    }).collect();
+    let required: Vec<Json> = keys.iter().filter(|key| {
+        tables.iter().all(|table| table.contains_key(**key))
+    }).map(|key| string(key)).collect();
+    let mut result = vec![
+        (String::from("type"), string("object")),
+        (String::from("properties"), Json::Object(properties)),
+    ];
+    if !required.is_empty() {
+        result.push((String::from("required"), Json::Array(required)));
+    }
+    result
+}
+
+// Schema that all of `items` fit, as precise as their types allow
+fn infer_items(items: &[&Item]) -> Json {
+    fn unwrap(item: &Item) -> &Item {
+        match item {
+            Item::Item(item) => unwrap(item),
+            _ => item,
+        }
+    }
+    let items: Vec<&Item> = items.iter().map(|item| unwrap(item)).collect();
+    if let Some(tables) = items.iter().map(|item| match item {
+        Item::Table(table) => Some(table),
+        _ => None,
+    }).collect::<Option<Vec<_>>>() {
+        return Json::Object(infer_tables(&tables));
+    }
+    if let Some(lists) = items.iter().map(|item| match item {
+        Item::List(list) => Some(list),
+        _ => None,
+    }).collect::<Option<Vec<_>>>() {
+        let all: Vec<&Item> = lists.into_iter().flatten().collect();
+        let mut result = vec![(String::from("type"), string("array"))];
+        if !all.is_empty() {
+            result.push((String::from("items"), infer_items(&all)));
+        }
+        return Json::Object(result);
+    }
+    let mut names: Vec<&str> = items.iter().filter_map(|item| match item {
+        Item::Text(_) | Item::Reference { .. } | Item::Template { .. } => Some("string"),
+        Item::Number(Number::USIZE { .. }) => Some("integer"),
+        Item::Number(Number::FLOAT { .. }) => Some("number"),
+        Item::Boolean(_) => Some("boolean"),
+        Item::Table(_) => Some("object"),
+        Item::List(_) => Some("array"),
+        Item::Item(_) => None,
+    }).collect();
+    names.sort();
+    names.dedup();
+    match names.as_slice() {
+        [] => Json::Object(Vec::new()),
+        [name] => json_object! { "type" => string(name) },
+        names => json_object! { "type" => Json::Array(names.iter().map(|name| string(name)).collect()) },
+    }
+}
+
+// JSON Schema that the JSON output of `table` fits
+pub fn infer(table: &HashMap<String, Item>) -> Json {
+    let mut result = vec![(String::from("$schema"), string(DIALECT))];
+    result.extend(infer_tables(&[table]));
+    Json::Object(result)
+}
@@ -0,0 +1,252 @@
+// Style and correctness checks for a smartconf file.
+//
+// Rules look at the tokens of the file and at the provenance of the
+// keys it generates. A file can turn rules off (and back on) with
+//
+//     # smartconf-lint: disable long-line, snake-case
+//     # smartconf-lint: enable long-line
+//
+// `all` stands for every rule. The last comment for a rule wins and
+// applies to the whole file.
+
+use crate::cst::Cst;
+use crate::{InputFormat, Loc, Provenance, Tok, TokValue};
+
+pub const RULES: [&str; 7] = [
+    "unused-include",
+    "shadowed-key",
+    "mixed-quotes",
+    "snake-case",
+    "trailing-whitespace",
+    "long-line",
+    "string-boolean",
+];
+
+const MAX_LINE_LENGTH: usize = 100;
+
+const MAGIC_COMMENT: &str = "smartconf-lint:";
+
+pub struct Finding {
+    pub loc: Loc,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+fn is_specchar(tok: Option<&Tok>, specchar: &str) -> bool {
+    matches!(tok, Some(Tok { value: TokValue::SPECCHAR { ref value }, .. }) if value == specchar)
+}
+
+fn is_snake_case(key: &str) -> bool {
+    match key.chars().next() {
+        Some(c) if c.is_ascii_lowercase() || c == '_' => {},
+        _ => return false,
+    }
+    key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// Rules enabled by the magic comments of `cst`
+fn enabled_rules(cst: &Cst) -> Vec<&'static str> {
+    let mut enabled: Vec<&'static str> = RULES.to_vec();
+    for tok in &cst.toks {
+        let comment = match tok.value {
+            TokValue::ONE_LINE_COMMENT { ref value } => value.trim(),
+            _ => continue,
+        };
+        let directive = match comment.strip_prefix(MAGIC_COMMENT) {
+            Some(directive) => directive.trim(),
+            None => continue,
+        };
+        let (enable, rules) = if let Some(rules) = directive.strip_prefix("enable") {
+            (true, rules)
+        } else if let Some(rules) = directive.strip_prefix("disable") {
+            (false, rules)
+        } else {
+            warning!("lint: {}: {}: {}: Expected `enable` or `disable`", tok.loc.filename, tok.loc.line_number, tok.loc.column_number);
+            continue;
+        };
+        for name in rules.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            let selected: Vec<&'static str> = match name {
+                "all" => RULES.to_vec(),
+                _ => match RULES.iter().find(|rule| **rule == name) {
+                    Some(rule) => vec![*rule],
+                    None => {
+                        warning!("lint: {}: {}: {}: Unknown rule: `{}`", tok.loc.filename, tok.loc.line_number, tok.loc.column_number, name);
+                        continue;
+                    },
+                },
+            };
+            enabled.retain(|rule| !selected.contains(rule));
+            if enable {
+                enabled.extend(selected);
+            }
+        }
+    }
+    enabled
+}
+
+// Format and file name of the `include` whose keyword is `toks[position]`,
+// if it is one
+fn include(toks: &[&Tok], position: usize) -> Option<(Option<InputFormat>, String)> {
+    if !matches!(toks[position].value, TokValue::IDENTIFIER { ref value } if value == "include") {
+        return None;
+    }
+    match (toks.get(position + 1).map(|tok| &tok.value), toks.get(position + 2).map(|tok| &tok.value)) {
+        (Some(TokValue::STRING { value, .. }), _) => Some((None, value.clone())),
+        (Some(TokValue::IDENTIFIER { value: format }), Some(TokValue::STRING { value, .. })) => {
+            Some((InputFormat::from_name(format), value.clone()))
+        },
+        _ => None,
+    }
+}
+
+// Formats and file names of the `include`s in `toks`
+fn includes(toks: &[Tok]) -> Vec<(Option<InputFormat>, String)> {
+    let toks: Vec<&Tok> = toks.iter().filter(|tok| {
+        !matches!(tok.value, TokValue::ONE_LINE_COMMENT { .. })
+    }).collect();
+    (0..toks.len()).filter_map(|position| include(&toks, position)).collect()
+}
+
+// `file_name` and the files it includes, directly or not
+fn reachable_files(program_name: &str, config: &crate::Config, format: Option<InputFormat>, file_name: &str, result: &mut Vec<String>) {
+    if result.iter().any(|reached| reached == file_name) {
+        return;
+    }
+    result.push(file_name.to_string());
+    // Imported files include nothing
+    if format.unwrap_or_else(|| InputFormat::of_file(file_name)) != InputFormat::Smartconf {
+        return;
+    }
+    let source = match crate::read_input(file_name) {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+    let toks = crate::lex_source(program_name.to_string(), crate::Config {
+        input_file_name: Some(file_name.to_string()),
+        starting_line_number: 1,
+        starting_column_number: 1,
+        display_file_name: None,
+        ..config.clone()
+    }, source);
+    for (format, include) in includes(&toks) {
+        reachable_files(program_name, config, format, &include, result);
+    }
+}
+
+// Findings for the file of `cst`, sorted by location. `provenance` is
+// filled by generating that file; `file_name` is its name in messages.
+pub fn lint(program_name: &str, config: &crate::Config, cst: &Cst, provenance: &Provenance, file_name: &str) -> Vec<Finding> {
+    let enabled = enabled_rules(cst);
+    let mut findings: Vec<Finding> = Vec::new();
+    macro_rules! report {
+        ($rule: expr, $loc: expr, $($msg: expr),+) => {
+            if enabled.contains(&$rule) {
+                findings.push(Finding {
+                    loc: $loc.clone(),
+                    rule: $rule,
+                    message: format!($($msg,)+),
+                });
+            }
+        };
+    }
+
+    let toks: Vec<(usize, &Tok)> = cst.toks.iter().enumerate().filter(|(_, tok)| {
+        !matches!(tok.value, TokValue::ONE_LINE_COMMENT { .. })
+    }).collect();
+    let significant: Vec<&Tok> = toks.iter().map(|(_, tok)| *tok).collect();
+    for (position, (index, tok)) in toks.iter().enumerate() {
+        if let TokValue::IDENTIFIER { .. } = tok.value {
+            let (format, include) = match include(&significant, position) {
+                Some(include) => include,
+                None => continue,
+            };
+            let mut files = Vec::new();
+            reachable_files(program_name, config, format, &include, &mut files);
+            if !provenance.keys.values().any(|loc| files.contains(&loc.filename)) {
+                report!("unused-include", tok.loc, "Nothing from `{}` is left in the result", include);
+            }
+            continue;
+        }
+        let value = match tok.value {
+            TokValue::STRING { ref value, .. } => value,
+            _ => continue,
+        };
+        let previous = match position {
+            0 => None,
+            _ => Some(toks[position - 1].1),
+        };
+        if (value == "true" || value == "false")
+            && (is_specchar(previous, ":") || is_specchar(previous, "[") || is_specchar(previous, ","))
+        {
+            report!("string-boolean", tok.loc, "Boolean written as a string: `{}`", cst.text(*index));
+        }
+    }
+
+    for entry in cst.entries() {
+        for index in entry.key.clone() {
+            let tok = &cst.toks[index];
+            if let TokValue::IDENTIFIER { ref value } | TokValue::STRING { ref value, .. } = tok.value {
+                if !is_snake_case(value) {
+                    report!("snake-case", tok.loc, "Key is not snake_case: `{}`", value);
+                }
+            }
+        }
+        let tok = &cst.toks[entry.value.start];
+        if !matches!(tok.value, TokValue::STRING { .. } | TokValue::NUMBER { .. } | TokValue::BOOLEAN { .. } | TokValue::REFERENCE { .. }) {
+            continue;
+        }
+        let key = crate::key_path_to_string(&entry.path);
+        match provenance.keys.get(&entry.path) {
+            Some(loc) if loc.filename == tok.loc.filename
+                && loc.line_number == tok.loc.line_number
+                && loc.column_number == tok.loc.column_number => {},
+            Some(loc) => {
+                report!("shadowed-key", tok.loc, "`{}` is overridden at {}: {}: {}", key, loc.filename, loc.line_number, loc.column_number);
+            },
+            None => {
+                report!("shadowed-key", tok.loc, "`{}` is removed later", key);
+            },
+        }
+    }
+
+    let mut file_quote_type: Option<char> = None;
+    for tok in &cst.toks {
+        let (value, quote_type) = match tok.value {
+            TokValue::STRING { ref value, quote_type } => (value, quote_type),
+            _ => continue,
+        };
+        match file_quote_type {
+            None => {
+                file_quote_type = Some(quote_type);
+            },
+            // Another quote type is fine when it saves escaping
+            Some(file_quote_type) if quote_type != file_quote_type && !value.contains(file_quote_type) => {
+                report!("mixed-quotes", tok.loc, "Use `{}` quotes like the rest of the file", file_quote_type);
+            },
+            Some(_) => {},
+        }
+    }
+
+    for (number, line) in cst.source.lines().enumerate() {
+        let line_number = number + config.starting_line_number;
+        let length = line.chars().count();
+        if line.ends_with(' ') || line.ends_with('\t') {
+            let column_number = line.trim_end_matches([' ', '\t']).chars().count() + config.starting_column_number;
+            report!("trailing-whitespace", Loc {
+                filename: file_name.to_string(),
+                line_number,
+                column_number,
+            }, "Trailing whitespace");
+        }
+        if length > MAX_LINE_LENGTH {
+            report!("long-line", Loc {
+                filename: file_name.to_string(),
+                line_number,
+                column_number: MAX_LINE_LENGTH + config.starting_column_number,
+            }, "Line is longer than {} characters", MAX_LINE_LENGTH);
+        }
+    }
+
+    findings.sort_by_key(|finding| (finding.loc.line_number, finding.loc.column_number));
+    findings
+}
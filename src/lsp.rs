@@ -0,0 +1,616 @@
+// Language server for smartconf files, over stdio.
+//
+// Diagnostics, hover and definitions need the generated result, and
+// the generator exits the process on the first error, so the server
+// runs itself on each document (`smartconf - --file-name PATH`) and
+// reads the messages it prints; when that run fails, hover and
+// definitions fall back to the keys defined in the document itself.
+// Document symbols come from the tokens of the document, which are
+// lexed in-process only when that run reported no lexer error in the
+// document.
+
+use ::std::io::{BufRead, Write};
+
+use crate::cst::Cst;
+use crate::json::{self, json_object, Json};
+use crate::{Config, Reference, TokValue};
+
+struct Document {
+    path: String,
+    text: String,
+    cst: Option<Cst>,
+}
+
+// An error printed by a run of the server itself
+struct Message {
+    filename: Option<String>,
+    line_number: usize,
+    column_number: usize,
+    text: String,
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<Result<Json, String>> {
+    let mut content_length: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {},
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse().ok();
+            }
+        }
+    }
+    let content_length = match content_length {
+        Some(content_length) => content_length,
+        None => return Some(Err(String::from("Missing Content-Length"))),
+    };
+    let mut content = vec![0; content_length];
+    if input.read_exact(&mut content).is_err() {
+        return None;
+    }
+    Some(match String::from_utf8(content) {
+        Ok(content) => json::parse(&content),
+        Err(_) => Err(String::from("Message is not UTF-8")),
+    })
+}
+
+fn send(output: &mut impl Write, message: &Json) {
+    let content = message.to_string();
+    // The client is gone if this fails, and `exit` will not come
+    if write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content).is_err() || output.flush().is_err() {
+        ::std::process::exit(1);
+    }
+}
+
+fn number(n: usize) -> Json {
+    Json::Number(n.to_string())
+}
+
+fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let mut result = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() && bytes[index + 1].is_ascii_hexdigit() && bytes[index + 2].is_ascii_hexdigit() {
+            let hex = |byte: u8| (byte as char).to_digit(16).unwrap() as u8;
+            result.push(hex(bytes[index + 1]) * 16 + hex(bytes[index + 2]));
+            index += 3;
+            continue;
+        }
+        result.push(bytes[index]);
+        index += 1;
+    }
+    String::from_utf8_lossy(&result).to_string()
+}
+
+fn path_to_uri(path: &str) -> String {
+    let path = match ::std::env::current_dir() {
+        Ok(dir) if !path.starts_with('/') => dir.join(path).to_string_lossy().to_string(),
+        _ => path.to_string(),
+    };
+    let mut result = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => result.push(byte as char),
+            _ => result += &format!("%{:02X}", byte),
+        }
+    }
+    result
+}
+
+// LSP position (0-based line, UTF-16 column) of byte `offset` of `text`
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset];
+    let line_start = match before.rfind('\n') {
+        Some(index) => index + 1,
+        None => 0,
+    };
+    json_object! {
+        "line" => number(before.matches('\n').count()),
+        "character" => number(before[line_start..].encode_utf16().count()),
+    }
+}
+
+// Byte offset of an LSP position in `text`
+fn offset(text: &str, position: &Json) -> Option<usize> {
+    let line = position.get("line")?.as_usize()?;
+    let character = position.get("character")?.as_usize()?;
+    let mut line_start = 0;
+    for _ in 0..line {
+        line_start += text[line_start..].find('\n')? + 1;
+    }
+    let mut units = 0;
+    for (index, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + index);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+fn loc_range(line_number: usize, column_number: usize) -> Json {
+    let start = json_object! {
+        "line" => number(line_number.saturating_sub(1)),
+        "character" => number(column_number.saturating_sub(1)),
+    };
+    let end = json_object! {
+        "line" => number(line_number.saturating_sub(1)),
+        "character" => number(column_number),
+    };
+    json_object! { "start" => start, "end" => end }
+}
+
+fn token_range(cst: &Cst, range: ::std::ops::Range<usize>) -> Json {
+    let start = cst.toks[range.start].span.start;
+    let end = match range.end {
+        0 => start,
+        end => cst.toks[end - 1].span.end.max(start),
+    };
+    json_object! {
+        "start" => position(&cst.source, start),
+        "end" => position(&cst.source, end),
+    }
+}
+
+// Runs this program on `text` as if it were the file `path`;
+// returns its stdout and stderr
+fn run_self(config: &Config, path: &str, text: &str, args: &[&str]) -> Option<(String, String)> {
+    let mut command = ::std::process::Command::new(::std::env::current_exe().ok()?);
+    command.args(args).arg("--file-name").arg(path);
+    if config.no_env {
+        command.arg("--no-env");
+    }
+    for (key, value) in &config.parameters {
+        command.arg("-D").arg(format!("{}={}", key, value));
+    }
+    if !config.arguments.is_empty() {
+        command.arg("--").args(&config.arguments);
+    }
+    let mut child = command
+        .stdin(::std::process::Stdio::piped())
+        .stdout(::std::process::Stdio::piped())
+        .stderr(::std::process::Stdio::piped())
+        .spawn()
+        .ok()?;
+    let mut stdin = child.stdin.take()?;
+    let text = text.to_string();
+    // Written from another thread so that a full stderr pipe cannot
+    // block the child before it has read all of its input
+    let writer = ::std::thread::spawn(move || {
+        let _ = stdin.write_all(text.as_bytes());
+    });
+    let output = child.wait_with_output().ok()?;
+    let _ = writer.join();
+    Some((
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    ))
+}
+
+// Errors in `stderr`, like `error: STAGE: FILE: LINE: COLUMN: TEXT`
+fn errors(stderr: &str) -> Vec<(String, Message)> {
+    let mut result = Vec::new();
+    for line in stderr.lines() {
+        let line = match line.strip_prefix("error: ") {
+            Some(line) => line,
+            None => continue,
+        };
+        let parts: Vec<&str> = line.split(": ").collect();
+        let stage = parts[0].to_string();
+        let located = (1..parts.len().saturating_sub(3)).find(|index| {
+            parts[index + 1].parse::<usize>().is_ok() && parts[index + 2].parse::<usize>().is_ok()
+        });
+        let message = match located {
+            Some(index) => Message {
+                filename: Some(parts[index].to_string()),
+                line_number: parts[index + 1].parse().unwrap(),
+                column_number: parts[index + 2].parse().unwrap(),
+                text: parts[index + 3..].join(": "),
+            },
+            None => Message {
+                filename: None,
+                line_number: 1,
+                column_number: 1,
+                text: parts[1..].join(": "),
+            },
+        };
+        result.push((stage, message));
+    }
+    result
+}
+
+// Where each key of the result was defined
+fn provenance(config: &Config, document: &Document) -> Vec<(Vec<String>, Message)> {
+    let stdout = match run_self(config, &document.path, &document.text, &["-", "--provenance"]) {
+        Some((stdout, _)) => stdout,
+        None => return Vec::new(),
+    };
+    let mut result = Vec::new();
+    for line in stdout.lines() {
+        let parts: Vec<&str> = line.rsplitn(4, ": ").collect();
+        if parts.len() != 4 {
+            continue;
+        }
+        let path = match crate::parse_key_path(parts[3]) {
+            Some(path) => path,
+            None => continue,
+        };
+        result.push((path, Message {
+            filename: Some(parts[2].to_string()),
+            line_number: parts[1].parse().unwrap_or(1),
+            column_number: parts[0].parse().unwrap_or(1),
+            text: String::new(),
+        }));
+    }
+    result
+}
+
+fn publish_diagnostics(output: &mut impl Write, config: &Config, program_name: &str, uri: &str, document: &mut Document) {
+    let stderr = match run_self(config, &document.path, &document.text, &["-", "--format", "json"]) {
+        Some((_, stderr)) => stderr,
+        None => String::new(),
+    };
+    let mut diagnostics = Vec::new();
+    let mut lexable = true;
+    for (stage, message) in errors(&stderr) {
+        let here = message.filename.as_deref() == Some(document.path.as_str());
+        if stage == "lexer" && here {
+            lexable = false;
+        }
+        let (range, text) = match (&message.filename, here) {
+            (Some(_), true) => (loc_range(message.line_number, message.column_number), message.text),
+            (Some(filename), false) => (loc_range(1, 1), format!("{}: {}: {}: {}", filename, message.line_number, message.column_number, message.text)),
+            (None, _) => (loc_range(1, 1), message.text),
+        };
+        diagnostics.push(json_object! {
+            "range" => range,
+            "severity" => number(1),
+            "source" => Json::String(String::from("smartconf")),
+            "message" => Json::String(format!("{}: {}", stage, text)),
+        });
+    }
+    document.cst = match lexable {
+        true => {
+            let lexed = crate::lex_source(program_name.to_string(), Config {
+                input_file_name: Some(document.path.clone()),
+                display_file_name: None,
+                starting_line_number: 1,
+                starting_column_number: 1,
+                ..config.clone()
+            }, document.text.clone());
+            Some(Cst::new(document.text.clone(), lexed))
+        },
+        false => None,
+    };
+    send(output, &json_object! {
+        "jsonrpc" => Json::String(String::from("2.0")),
+        "method" => Json::String(String::from("textDocument/publishDiagnostics")),
+        "params" => json_object! {
+            "uri" => Json::String(uri.to_string()),
+            "diagnostics" => Json::Array(diagnostics),
+        },
+    });
+}
+
+enum Target {
+    Include(String),
+    Key(Vec<String>),
+}
+
+// What the token at byte `offset` of the document refers to
+fn target(cst: &Cst, offset: usize) -> Option<Target> {
+    let index = cst.toks.iter().position(|tok| tok.span.contains(&offset))?;
+    let tok = &cst.toks[index];
+    let mut before = cst.toks[..index].iter().rev().filter(|tok| {
+        !matches!(tok.value, TokValue::ONE_LINE_COMMENT { .. })
+    }).map(|tok| match tok.value {
+        TokValue::IDENTIFIER { ref value } => Some(value.as_str()),
+        _ => None,
+    });
+    // `include 'file'` or `include FORMAT 'file'`
+    let included = match before.next().flatten() {
+        Some("include") => true,
+        Some(_) => before.next().flatten() == Some("include"),
+        None => false,
+    };
+    match tok.value {
+        TokValue::STRING { ref value, .. } if included => {
+            return Some(Target::Include(value.clone()));
+        },
+        TokValue::REFERENCE { ref value } => {
+            return match crate::parse_reference(value)? {
+                Reference::Key(path) => Some(Target::Key(path)),
+                _ => None,
+            };
+        },
+        _ => {},
+    }
+    if let Some(entry) = cst.entries().into_iter().find(|entry| entry.key.contains(&index)) {
+        return Some(Target::Key(entry.path));
+    }
+    // `${...}` in a string
+    if let TokValue::STRING { .. } = tok.value {
+        let text = cst.text(index);
+        let cursor = offset - tok.span.start;
+        let start = text[..cursor].rfind("${")?;
+        let end = start + text[start..].find('}')?;
+        if end < cursor {
+            return None;
+        }
+        return match crate::parse_reference(&text[start + 2..end])? {
+            Reference::Key(path) => Some(Target::Key(path)),
+            _ => None,
+        };
+    }
+    None
+}
+
+// Where `path` is defined in the document itself, going by its own
+// entries: the last definition of the key, or else the first key below it
+fn local_definition(document: &Document, path: &[String]) -> Option<Message> {
+    let cst = document.cst.as_ref()?;
+    let entries = cst.entries();
+    let entry = entries.iter().rev().find(|entry| entry.path == path)
+        .or_else(|| entries.iter().find(|entry| entry.path.starts_with(path)))?;
+    let before = &cst.source[..cst.toks[entry.key.start].span.start];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    Some(Message {
+        filename: Some(document.path.clone()),
+        line_number: before.matches('\n').count() + 1,
+        column_number: before[line_start..].chars().count() + 1,
+        text: String::new(),
+    })
+}
+
+// Source text of the last value of `path` in the document itself
+fn local_value(document: &Document, path: &[String]) -> Option<String> {
+    let cst = document.cst.as_ref()?;
+    let entry = cst.entries().into_iter().rev().find(|entry| entry.path == path)?;
+    let start = cst.toks[entry.value.start].span.start;
+    let end = cst.toks[entry.value.end - 1].span.end;
+    Some(cst.source[start..end].to_string())
+}
+
+// Where the value of `path` comes from: the key itself, or else the
+// first key below it. When the document cannot be generated, only keys
+// defined in the document itself are found.
+fn definition(config: &Config, document: &Document, path: &[String]) -> Option<Message> {
+    let mut keys = provenance(config, document);
+    if keys.is_empty() {
+        return local_definition(document, path);
+    }
+    keys.sort_by(|a, b| a.0.cmp(&b.0));
+    let exact = keys.iter().position(|(key, _)| key == path);
+    let index = exact.or_else(|| keys.iter().position(|(key, _)| key.starts_with(path)))?;
+    Some(keys.swap_remove(index).1)
+}
+
+fn location(document: &Document, uri: &str, message: &Message) -> Json {
+    let filename = message.filename.clone().unwrap_or_default();
+    let uri = match filename == document.path {
+        true => uri.to_string(),
+        false => path_to_uri(&filename),
+    };
+    json_object! {
+        "uri" => Json::String(uri),
+        "range" => loc_range(message.line_number, message.column_number),
+    }
+}
+
+fn symbol_kind(cst: &Cst, entry: &crate::cst::Entry) -> usize {
+    let mut index = entry.value.start;
+    if let Some(crate::Tok { value: TokValue::SPECCHAR { value }, .. }) = cst.toks.get(index) {
+        if value == "!" {
+            index += 2;
+        }
+    }
+    match cst.toks.get(index).map(|tok| &tok.value) {
+        Some(TokValue::SPECCHAR { value }) if value == "{" => 19,
+        Some(TokValue::SPECCHAR { value }) if value == "[" => 18,
+        Some(TokValue::STRING { .. }) => 15,
+        Some(TokValue::NUMBER { .. }) => 16,
+        Some(TokValue::BOOLEAN { .. }) => 17,
+        _ => 13,
+    }
+}
+
+// Symbols of the entries from `*index` that start before token `end`
+fn symbols(cst: &Cst, entries: &[crate::cst::Entry], index: &mut usize, end: usize) -> Vec<Json> {
+    let mut result = Vec::new();
+    while *index < entries.len() && entries[*index].key.start < end {
+        let entry = &entries[*index];
+        *index += 1;
+        let children = symbols(cst, entries, index, entry.value.end);
+        let name: String = entry.key.clone().map(|index| cst.text(index)).collect();
+        result.push(json_object! {
+            "name" => Json::String(name),
+            "kind" => number(symbol_kind(cst, entry)),
+            "range" => token_range(cst, entry.key.start..entry.value.end.max(entry.key.end)),
+            "selectionRange" => token_range(cst, entry.key.clone()),
+            "children" => Json::Array(children),
+        });
+    }
+    result
+}
+
+// Result of the request `method`, None if it is not supported
+fn handle_request(config: &Config, documents: &::std::collections::HashMap<String, Document>, method: &str, params: &Json) -> Option<Json> {
+    let uri = params.get("textDocument").and_then(|document| document.get("uri")).and_then(|uri| uri.as_str());
+    match method {
+        "initialize" => {
+            return Some(json_object! {
+                "capabilities" => json_object! {
+                    "textDocumentSync" => number(1),
+                    "definitionProvider" => Json::Boolean(true),
+                    "hoverProvider" => Json::Boolean(true),
+                    "documentSymbolProvider" => Json::Boolean(true),
+                },
+                "serverInfo" => json_object! {
+                    "name" => Json::String(String::from("smartconf")),
+                },
+            });
+        },
+        "shutdown" => {
+            return Some(Json::Null);
+        },
+        "textDocument/documentSymbol" => {
+            let cst = match documents.get(uri?) {
+                Some(Document { cst: Some(cst), .. }) => cst,
+                _ => return Some(Json::Null),
+            };
+            let entries = cst.entries();
+            return Some(Json::Array(symbols(cst, &entries, &mut 0, cst.toks.len())));
+        },
+        "textDocument/definition" | "textDocument/hover" => {},
+        _ => return None,
+    }
+    let uri = uri?;
+    let document = documents.get(uri)?;
+    let target = document.cst.as_ref().and_then(|cst| {
+        target(cst, offset(&document.text, params.get("position")?)?)
+    });
+    let result = match (method, target) {
+        ("textDocument/definition", Some(Target::Include(filename))) => {
+            json_object! {
+                "uri" => Json::String(path_to_uri(&filename)),
+                "range" => loc_range(1, 0),
+            }
+        },
+        ("textDocument/definition", Some(Target::Key(path))) => {
+            match definition(config, document, &path) {
+                Some(message) => location(document, uri, &message),
+                None => Json::Null,
+            }
+        },
+        ("textDocument/hover", Some(Target::Key(path))) => {
+            let key = crate::key_path_to_string(&path);
+            let value = match run_self(config, &document.path, &document.text, &["get", "-", &key, "--format", "json"]) {
+                Some((stdout, _)) if !stdout.is_empty() => stdout,
+                _ => match local_value(document, &path) {
+                    Some(value) => value + "\n",
+                    None => return Some(Json::Null),
+                },
+            };
+            let mut contents = format!("`{}`\n```\n{}```", key, value);
+            if let Some(message) = definition(config, document, &path) {
+                contents += &format!("\nDefined at {}: {}: {}", message.filename.unwrap_or_default(), message.line_number, message.column_number);
+            }
+            json_object! {
+                "contents" => json_object! {
+                    "kind" => Json::String(String::from("markdown")),
+                    "value" => Json::String(contents),
+                },
+            }
+        },
+        _ => Json::Null,
+    };
+    Some(result)
+}
+
+// Serves one client on stdin and stdout; returns the exit code
+pub fn run(program_name: String, config: Config) -> i32 {
+    let stdin = ::std::io::stdin();
+    let mut input = stdin.lock();
+    let stdout = ::std::io::stdout();
+    let mut output = stdout.lock();
+    let mut documents: ::std::collections::HashMap<String, Document> = ::std::collections::HashMap::new();
+    let mut shut_down = false;
+    while let Some(message) = read_message(&mut input) {
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                send(&mut output, &json_object! {
+                    "jsonrpc" => Json::String(String::from("2.0")),
+                    "id" => Json::Null,
+                    "error" => json_object! {
+                        "code" => Json::Number(String::from("-32700")),
+                        "message" => Json::String(e),
+                    },
+                });
+                continue;
+            },
+        };
+        let method = message.get("method").and_then(|method| method.as_str()).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        if let Some(id) = message.get("id") {
+            let response = match handle_request(&config, &documents, method, &params) {
+                Some(result) => json_object! {
+                    "jsonrpc" => Json::String(String::from("2.0")),
+                    "id" => id.clone(),
+                    "result" => result,
+                },
+                None => json_object! {
+                    "jsonrpc" => Json::String(String::from("2.0")),
+                    "id" => id.clone(),
+                    "error" => json_object! {
+                        "code" => Json::Number(String::from("-32601")),
+                        "message" => Json::String(format!("Unsupported method: {}", method)),
+                    },
+                },
+            };
+            send(&mut output, &response);
+            if method == "shutdown" {
+                shut_down = true;
+            }
+            continue;
+        }
+        let text_document = params.get("textDocument");
+        let uri = match text_document.and_then(|document| document.get("uri")).and_then(|uri| uri.as_str()) {
+            Some(uri) => uri.to_string(),
+            None if method == "exit" => return if shut_down { 0 } else { 1 },
+            None => continue,
+        };
+        let text = match method {
+            "textDocument/didOpen" => text_document.and_then(|document| document.get("text")),
+            "textDocument/didChange" => params.get("contentChanges").and_then(|changes| changes.as_array()?.last()?.get("text")),
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                send(&mut output, &json_object! {
+                    "jsonrpc" => Json::String(String::from("2.0")),
+                    "method" => Json::String(String::from("textDocument/publishDiagnostics")),
+                    "params" => json_object! {
+                        "uri" => Json::String(uri),
+                        "diagnostics" => Json::Array(Vec::new()),
+                    },
+                });
+                continue;
+            },
+            _ => None,
+        };
+        let text = match text.and_then(|text| text.as_str()) {
+            Some(text) => text.to_string(),
+            None => continue,
+        };
+        let mut document = Document {
+            path: uri_to_path(&uri),
+            text,
+            cst: None,
+        };
+        publish_diagnostics(&mut output, &config, &program_name, &uri, &mut document);
+        documents.insert(uri, document);
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_paths() {
+        assert_eq!(uri_to_path("file:///tmp/a%20b.smf"), "/tmp/a b.smf");
+        assert_eq!(uri_to_path("file:///tmp/%E2%82%AC.smf"), "/tmp/€.smf");
+        assert_eq!(uri_to_path("file:///tmp/t/%€.smf"), "/tmp/t/%€.smf");
+        assert_eq!(uri_to_path("file:///tmp/%+1%4"), "/tmp/%+1%4");
+        assert_eq!(uri_to_path(&path_to_uri("/tmp/a b/€%.smf")), "/tmp/a b/€%.smf");
+    }
+}
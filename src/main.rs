@@ -8,6 +8,17 @@ macro_rules! short_usage {
         eprintln!("{}: [OPTION]... FILE", $program_name);
         eprintln!("{}: FILE [OPTION]...", $program_name);
         eprintln!("{}: FILE [OPTION]... -- [ARGUMENT]...", $program_name);
+        eprintln!("{}: FILE... [OPTION]...", $program_name);
+        eprintln!("{}: get FILE... KEY [OPTION]...", $program_name);
+        eprintln!("{}: set FILE KEY VALUE [OPTION]...", $program_name);
+        eprintln!("{}: fmt [--check] FILE... [OPTION]...", $program_name);
+        eprintln!("{}: lint FILE... [OPTION]...", $program_name);
+        eprintln!("{}: lsp [OPTION]...", $program_name);
+        eprintln!("{}: validate [--schema SCHEMA] FILE... [OPTION]...", $program_name);
+        eprintln!("{}: json-schema --schema SCHEMA [OPTION]...", $program_name);
+        eprintln!("{}: json-schema FILE... [OPTION]...", $program_name);
+        eprintln!("With FILE -, read standard input.");
+        eprintln!("Several FILEs are merged in order, like includes.");
     };
 }
 
@@ -30,13 +41,56 @@ macro_rules! options {
     () => {
         eprintln!("Options (case sensitive):");
         eprintln!("--help -h       Show this help message");
+        eprintln!("--output FILE");
+        eprintln!("-o FILE         Write output to FILE instead of stdout");
+        eprintln!("                (FILE is replaced atomically)");
         eprintln!("--format FORMAT");
         eprintln!("                Set format to FORMAT.");
         eprintln!("                Available formats listed below.");
+        eprintln!("--from FORMAT   Read each FILE in the input format");
+        eprintln!("                FORMAT (listed below)");
+        eprintln!("--separator SEPARATOR");
+        eprintln!("                With the env and sh formats, join");
+        eprintln!("                nested keys with SEPARATOR (default: _)");
+        eprintln!("--uppercase     With the env and sh formats, uppercase");
+        eprintln!("                the variable names");
         eprintln!("--variable-name NAME");
         eprintln!("-N NAME");
         eprintln!("                Set variable name to NAME");
         eprintln!("                (see README.md for details)");
+        eprintln!("--no-env        Forbid reading environment variables");
+        eprintln!("                with ${{env:NAME}}");
+        eprintln!("--define KEY=VALUE");
+        eprintln!("-D KEY=VALUE");
+        eprintln!("                Set KEY to VALUE, overriding the files.");
+        eprintln!("                Also available as ${{param:KEY}}");
+        eprintln!("--starting-line NUMBER");
+        eprintln!("--starting-column NUMBER");
+        eprintln!("                Number lines (columns) of FILE from");
+        eprintln!("                NUMBER in messages (default: 1)");
+        eprintln!("--file-name NAME");
+        eprintln!("                Use NAME instead of FILE in messages");
+        eprintln!("                (with several FILEs, these apply to");
+        eprintln!("                the first one)");
+        eprintln!("--check         With fmt, do not write any FILE, fail");
+        eprintln!("                if some FILE is not formatted");
+        eprintln!("--schema SCHEMA Use the schema file SCHEMA instead of the");
+        eprintln!("                `$schema` key of FILE");
+        eprintln!("--no-defaults   Do not add the defaults of the schema");
+        eprintln!("                for missing keys");
+        eprintln!("--watch         Generate again whenever FILE or a file");
+        eprintln!("                it includes changes, until interrupted");
+        eprintln!("--dependencies DEPFILE");
+        eprintln!("-M DEPFILE      Write a Make rule to DEPFILE saying that");
+        eprintln!("                the -o file depends on FILE and all the");
+        eprintln!("                files it includes");
+        eprintln!("--list-files LIST");
+        eprintln!("                Write the names of FILE and all the");
+        eprintln!("                files it includes to LIST, one per line");
+        eprintln!("--provenance    Print where each key was defined");
+        eprintln!("                instead of the output");
+        eprintln!("-- ARGUMENT...  Pass arguments, available as ${{arg:1}},");
+        eprintln!("                ${{arg:2}}, ...");
     };
 }
 
@@ -45,6 +99,22 @@ macro_rules! formats {
         eprintln!("Aviable formats:");
         eprintln!("vim             Vimscript file");
         eprintln!("json            JavaScript object notation");
+        eprintln!("env             dotenv file, one NAME=value per line");
+        eprintln!("sh              POSIX shell, one `export NAME='value'`");
+        eprintln!("                per line");
+        eprintln!("smartconf       smartconf, one definition per line");
+    };
+}
+
+macro_rules! input_formats {
+    () => {
+        eprintln!("Input formats (by default, from the extension of FILE):");
+        eprintln!("smartconf       smartconf (any other extension)");
+        eprintln!("json            JavaScript object notation (.json)");
+        eprintln!("toml            TOML (.toml)");
+        eprintln!("dotenv          NAME=value lines (.env, .env.*)");
+        eprintln!("ini             INI, sections become tables (.ini,");
+        eprintln!("                .conf)");
     };
 }
 
@@ -56,6 +126,33 @@ macro_rules! errorcodes {
         eprintln!("2        Filesystem error");
         eprintln!("3        Syntax error");
         eprintln!("4        Escaping error");
+        eprintln!("5        Key not found");
+        eprintln!("6        Check failed");
+    };
+}
+
+macro_rules! commands {
+    () => {
+        eprintln!("Commands:");
+        eprintln!("get             Print the value of KEY: strings, numbers");
+        eprintln!("                and booleans as they are, tables and");
+        eprintln!("                lists in FORMAT");
+        eprintln!("set             Change the value of KEY in FILE to VALUE");
+        eprintln!("                (or add KEY), keeping comments and");
+        eprintln!("                formatting; writes FILE unless -o");
+        eprintln!("                is given");
+        eprintln!("fmt             Rewrite each FILE in canonical layout;");
+        eprintln!("                with --check, only list the FILEs");
+        eprintln!("                that are not formatted");
+        eprintln!("lint            Print style and correctness problems");
+        eprintln!("                in each FILE (see README.md for rules)");
+        eprintln!("lsp             Run a Language Server Protocol server");
+        eprintln!("                on stdin and stdout");
+        eprintln!("validate        Check FILE against the schema given with");
+        eprintln!("                --schema or in its `$schema` key");
+        eprintln!("json-schema     Print a JSON Schema for the JSON output:");
+        eprintln!("                SCHEMA (or the `$schema` of FILE)");
+        eprintln!("                converted, or else the shape of FILE");
     };
 }
 
@@ -63,10 +160,14 @@ macro_rules! help {
     ($program_name: expr) => {
         short_usage!($program_name);
         eprintln!();
+        commands!();
+        eprintln!();
         options!();
         eprintln!();
         formats!();
         eprintln!();
+        input_formats!();
+        eprintln!();
         errorcodes!();
     };
 }
@@ -85,76 +186,188 @@ macro_rules! warning {
     };
 }
 
-fn repr(config: &Config, s: String) -> String {
-    #[allow(unused_macros)]
-    macro_rules! repr_error {
-        ($($msg: expr),+) => {
-            error!("repr: {}", format!($($msg,)+));
-        };
-    }
+mod cst;
+mod dotenv;
+mod fmt;
+mod import;
+mod ini;
+mod json;
+mod json_schema;
+mod lint;
+mod lsp;
+mod regex;
+mod schema;
+mod toml;
+mod watch;
 
-    #[allow(unused_macros)]
-    macro_rules! repr_warning {
-        ($($msg: expr),+) => {
-            warning!("repr: {}", format!($($msg,)+));
-        };
+// Quotes and escapes `s` as a string literal of the output format
+fn repr(config: &Config, s: String) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => result += "\\\\",
+            '"' => result += "\\\"",
+            '\n' => result += "\\n",
+            '\t' => result += "\\t",
+            '\r' => result += "\\r",
+            _ => {
+                if c.is_control() {
+                    match config.format {
+                        Format::Vim | Format::JSON => {
+                            result += &format!("\\u{:04x}", c as u32);
+                        },
+                        Format::Env | Format::Sh | Format::Smartconf => unreachable!(),
+                    }
+                } else {
+                    result.push(c);
+                }
+            },
+        }
     }
+    result.push('"');
+    return result;
+}
 
-    enum State {
-        #[allow(non_camel_case_types)] NONE,
-        #[allow(non_camel_case_types)] BACKSLASH,
-    }
-    let mut state = State::NONE;
-    let mut result = String::new();
+// Quotes and escapes `s` as a smartconf string literal,
+// preferring `quote_type` for the quotes
+fn smartconf_repr(s: &str, quote_type: char) -> String {
+    let quote_type = if quote_type == '`' && s.contains('`') {
+        '\''
+    } else {
+        quote_type
+    };
+    let mut result = String::from(quote_type);
     for c in s.chars() {
-        match state {
-            State::NONE => {
-                if c == '\\' {
-                    state = State::BACKSLASH;
-                    continue;
+        match c {
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\t' => result += "\\t",
+            '\r' => result += "\\r",
+            '\0' => result += "\\0",
+            '\x07' => result += "\\a",
+            '\x08' => result += "\\b",
+            '\x0B' => result += "\\v",
+            '\x0C' => result += "\\f",
+            _ => {
+                if c == quote_type {
+                    result.push('\\');
                 }
                 result.push(c);
             },
-            State::BACKSLASH => {
-                result.push(match c {
-                    '\\' => '\\',
-                    'n' => '\n',
-                    't' => '\t',
-                    'r' => '\r',
-                    '0' => '\0',
-                    'a' => '\x07',
-                    'b' => '\x08',
-                    'v' => '\x0B',
-                    'f' => '\x0C',
-                    '\'' => '\'',
-                    '"' => '"',
-                    _ => {
-                        repr_error!("Unknown symbol after backslash: '{}'", c);
-                        ::std::process::exit(4);
-                    },
-                })
-            },
         }
     }
+    result.push(quote_type);
     return result;
 }
 
+// Quotes and escapes `key` as a dictionary key of the output format
+fn key_repr(config: &Config, key: String) -> String {
+    match config.format {
+        Format::Vim => {
+            if key.chars().any(|c| c.is_control()) {
+                return repr(config, key);
+            }
+            return format!("'{}'", key.replace('\'', "''"));
+        },
+        Format::JSON => {
+            return repr(config, key);
+        },
+        Format::Env | Format::Sh | Format::Smartconf => unreachable!(),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Format {
     Vim,
     JSON,
+    Env,
+    Sh,
+    Smartconf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InputFormat {
+    Smartconf,
+    JSON,
+    TOML,
+    INI,
+    Dotenv,
+}
+
+impl InputFormat {
+    fn from_name(name: &str) -> Option<InputFormat> {
+        match name {
+            "smartconf" => Some(InputFormat::Smartconf),
+            "json" => Some(InputFormat::JSON),
+            "toml" => Some(InputFormat::TOML),
+            "ini" => Some(InputFormat::INI),
+            "dotenv" => Some(InputFormat::Dotenv),
+            _ => None,
+        }
+    }
+
+    // Format of `file_name` going by its extension
+    fn of_file(file_name: &str) -> InputFormat {
+        let path = ::std::path::Path::new(file_name);
+        // `.env` and `.env.local` have no extension as far as `Path` knows
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            if name == ".env" || name.starts_with(".env.") {
+                return InputFormat::Dotenv;
+            }
+        }
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => InputFormat::JSON,
+            Some("toml") => InputFormat::TOML,
+            Some("ini" | "conf") => InputFormat::INI,
+            Some("env") => InputFormat::Dotenv,
+            _ => InputFormat::Smartconf,
+        }
+    }
 }
 
 
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Generate,
+    Get { key: Vec<String> },
+    Set { key: Vec<String>, value: String },
+    Fmt,
+    Lint,
+    Lsp,
+    Validate,
+    JsonSchema,
+}
+
 #[derive(Debug, Clone)]
 struct Config {
+    command: Command,
+    // The file being read right now
     input_file_name: Option<String>,
+    // The files given on the command line, merged in order
+    input_file_names: Vec<String>,
     arguments: Vec<String>,
     starting_line_number: usize,
     starting_column_number: usize,
     format: Format,
     help: bool,
     variable_name: String,
+    no_env: bool,
+    parameters: Vec<(String, String)>,
+    // Name of the input file in messages, if it differs from `input_file_name`
+    display_file_name: Option<String>,
+    output_file_name: Option<String>,
+    provenance: bool,
+    check: bool,
+    watch: bool,
+    // File to write the names of the files read to, as they are read
+    list_files: Option<String>,
+    dependency_file_name: Option<String>,
+    schema_file_name: Option<String>,
+    no_defaults: bool,
+    // Format of `input_file_name`, when not the one of its extension
+    input_format: Option<InputFormat>,
+    separator: String,
+    uppercase: bool,
 }
 
 // cla = command-line arguments
@@ -174,21 +387,48 @@ fn parse_cla(program_name: String, mut args: ::std::env::Args) -> Config {
     }
 
     let mut result = Config {
+        command: Command::Generate,
         input_file_name: None,
+        input_file_names: Vec::new(),
         arguments: Vec::new(),
         starting_line_number: 1,
         starting_column_number: 1,
         format: Format::Vim,
         help: false,
         variable_name: String::from("config"),
+        no_env: false,
+        parameters: Vec::new(),
+        display_file_name: None,
+        output_file_name: None,
+        provenance: false,
+        check: false,
+        watch: false,
+        list_files: None,
+        dependency_file_name: None,
+        schema_file_name: None,
+        no_defaults: false,
+        input_format: None,
+        separator: String::from("_"),
+        uppercase: false,
     };
     enum CLAOptionWithArgument {
         Format,
         VariableName,
+        Define,
+        StartingLine,
+        StartingColumn,
+        FileName,
+        Output,
+        ListFiles,
+        Dependencies,
+        Schema,
+        From,
+        Separator,
     }
     enum State {
         #[allow(non_camel_case_types)] NONE,
         #[allow(non_camel_case_types)] OPTION_ARGUMENT{option: CLAOptionWithArgument},
+        #[allow(non_camel_case_types)] ARGUMENTS,
     }
     let mut state = State::NONE;
     while args.len() != 0 {
@@ -253,6 +493,21 @@ fn parse_cla(program_name: String, mut args: ::std::env::Args) -> Config {
                                         option: CLAOptionWithArgument::VariableName,
                                     };
                                 },
+                                "D" => {
+                                    state = State::OPTION_ARGUMENT {
+                                        option: CLAOptionWithArgument::Define,
+                                    };
+                                },
+                                "o" => {
+                                    state = State::OPTION_ARGUMENT {
+                                        option: CLAOptionWithArgument::Output,
+                                    };
+                                },
+                                "M" => {
+                                    state = State::OPTION_ARGUMENT {
+                                        option: CLAOptionWithArgument::Dependencies,
+                                    };
+                                },
                                 _ => {
                                     cla_parser_error!("Unknown short option");
                                     ::std::process::exit(1);
@@ -266,7 +521,36 @@ fn parse_cla(program_name: String, mut args: ::std::env::Args) -> Config {
                         unreachable!();
                     },
                     ArgState::ARGUMENT { argument } => {
-                        result.input_file_name = Some(argument);
+                        match (argument.as_str(), &result.command) {
+                            ("get", Command::Generate) if result.input_file_names.is_empty() => {
+                                result.command = Command::Get { key: Vec::new() };
+                            },
+                            ("set", Command::Generate) if result.input_file_names.is_empty() => {
+                                result.command = Command::Set { key: Vec::new(), value: String::new() };
+                            },
+                            ("fmt", Command::Generate) if result.input_file_names.is_empty() => {
+                                result.command = Command::Fmt;
+                            },
+                            ("lint", Command::Generate) if result.input_file_names.is_empty() => {
+                                result.command = Command::Lint;
+                            },
+                            ("lsp", Command::Generate) if result.input_file_names.is_empty() => {
+                                result.command = Command::Lsp;
+                            },
+                            ("validate", Command::Generate) if result.input_file_names.is_empty() => {
+                                result.command = Command::Validate;
+                            },
+                            ("json-schema", Command::Generate) if result.input_file_names.is_empty() => {
+                                result.command = Command::JsonSchema;
+                            },
+                            _ => {
+                                result.input_file_names.push(argument);
+                            },
+                        }
+                    },
+                    ArgState::SHORT_OPTIONS { options } if options.is_empty() => {
+                        // `-` alone means standard input
+                        result.input_file_names.push(String::from("-"));
                     },
                     ArgState::SHORT_OPTIONS { mut options } => {
                         handle_short_options!(options);
@@ -284,6 +568,77 @@ fn parse_cla(program_name: String, mut args: ::std::env::Args) -> Config {
                                     option: CLAOptionWithArgument::VariableName,
                                 };
                             },
+                            "no-env" => {
+                                result.no_env = true;
+                            },
+                            "provenance" => {
+                                result.provenance = true;
+                            },
+                            "check" => {
+                                result.check = true;
+                            },
+                            "watch" => {
+                                result.watch = true;
+                            },
+                            "no-defaults" => {
+                                result.no_defaults = true;
+                            },
+                            "list-files" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::ListFiles,
+                                };
+                            },
+                            "dependencies" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::Dependencies,
+                                };
+                            },
+                            "schema" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::Schema,
+                                };
+                            },
+                            "from" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::From,
+                                };
+                            },
+                            "separator" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::Separator,
+                                };
+                            },
+                            "uppercase" => {
+                                result.uppercase = true;
+                            },
+                            "define" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::Define,
+                                };
+                            },
+                            "starting-line" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::StartingLine,
+                                };
+                            },
+                            "starting-column" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::StartingColumn,
+                                };
+                            },
+                            "file-name" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::FileName,
+                                };
+                            },
+                            "output" => {
+                                state = State::OPTION_ARGUMENT {
+                                    option: CLAOptionWithArgument::Output,
+                                };
+                            },
+                            "" => {
+                                state = State::ARGUMENTS;
+                            },
                             _ => {
                                 cla_parser_error!("Unknown long option");
                                 ::std::process::exit(1);
@@ -299,6 +654,9 @@ fn parse_cla(program_name: String, mut args: ::std::env::Args) -> Config {
                         result.format = match arg.as_str() {
                             "vim" => Format::Vim,
                             "json" => Format::JSON,
+                            "env" => Format::Env,
+                            "sh" => Format::Sh,
+                            "smartconf" => Format::Smartconf,
                             _ => {
                                 cla_parser_error!("Unknown format: \"{}\"", arg);
                                 eprintln!();
@@ -312,11 +670,153 @@ fn parse_cla(program_name: String, mut args: ::std::env::Args) -> Config {
                         result.variable_name = arg;
                         state = State::NONE;
                     },
+                    CLAOptionWithArgument::Define => {
+                        let (key, value) = match arg.split_once('=') {
+                            Some((key, value)) => (key, value),
+                            None => {
+                                cla_parser_error!("Expected KEY=VALUE, found: \"{}\"", arg);
+                                ::std::process::exit(1);
+                            },
+                        };
+                        if parse_key_path(key).is_none() {
+                            cla_parser_error!("Invalid key: \"{}\"", key);
+                            ::std::process::exit(1);
+                        }
+                        result.parameters.push((key.to_string(), value.to_string()));
+                        state = State::NONE;
+                    },
+                    CLAOptionWithArgument::StartingLine | CLAOptionWithArgument::StartingColumn => {
+                        let number = match str_to_usize(&arg) {
+                            Some(number) if number != 0 => number,
+                            _ => {
+                                cla_parser_error!("Expected a positive number, found: \"{}\"", arg);
+                                ::std::process::exit(1);
+                            },
+                        };
+                        match option {
+                            CLAOptionWithArgument::StartingLine => {
+                                result.starting_line_number = number;
+                            },
+                            _ => {
+                                result.starting_column_number = number;
+                            },
+                        }
+                        state = State::NONE;
+                    },
+                    CLAOptionWithArgument::FileName => {
+                        result.display_file_name = Some(arg);
+                        state = State::NONE;
+                    },
+                    CLAOptionWithArgument::Output => {
+                        result.output_file_name = Some(arg);
+                        state = State::NONE;
+                    },
+                    CLAOptionWithArgument::ListFiles => {
+                        result.list_files = Some(arg);
+                        state = State::NONE;
+                    },
+                    CLAOptionWithArgument::Dependencies => {
+                        result.dependency_file_name = Some(arg);
+                        state = State::NONE;
+                    },
+                    CLAOptionWithArgument::Schema => {
+                        result.schema_file_name = Some(arg);
+                        state = State::NONE;
+                    },
+                    CLAOptionWithArgument::Separator => {
+                        result.separator = arg;
+                        state = State::NONE;
+                    },
+                    CLAOptionWithArgument::From => {
+                        result.input_format = match InputFormat::from_name(&arg) {
+                            Some(input_format) => Some(input_format),
+                            None => {
+                                cla_parser_error!("Unknown input format: \"{}\"", arg);
+                                eprintln!();
+                                input_formats!();
+                                ::std::process::exit(1);
+                            },
+                        };
+                        state = State::NONE;
+                    },
                     _ => unreachable!(),
                 }
             },
+            State::ARGUMENTS => {
+                result.arguments.push(arg);
+            },
         }
     }
+    if result.check && !matches!(result.command, Command::Fmt) {
+        cla_parser_error!("--check is only available with fmt");
+        ::std::process::exit(1);
+    }
+    if result.dependency_file_name.is_some() && !matches!(result.output_file_name.as_deref(), Some(name) if name != "-") {
+        cla_parser_error!("-M needs an output file (-o)");
+        ::std::process::exit(1);
+    }
+    if result.watch && !matches!(result.command, Command::Generate) {
+        cla_parser_error!("--watch is only available when generating");
+        ::std::process::exit(1);
+    }
+    if result.watch && result.input_file_names.iter().any(|file_name| file_name == "-") {
+        cla_parser_error!("--watch cannot watch standard input");
+        ::std::process::exit(1);
+    }
+    match result.command {
+        Command::Generate | Command::Validate | Command::JsonSchema => {},
+        Command::Fmt => {
+            if result.input_file_names.is_empty() {
+                cla_parser_error!("fmt: Expected FILE...");
+                ::std::process::exit(1);
+            }
+            if result.input_file_names.len() > 1 && result.output_file_name.is_some() {
+                cla_parser_error!("fmt: -o needs exactly one FILE");
+                ::std::process::exit(1);
+            }
+        },
+        Command::Lint => {
+            if result.input_file_names.is_empty() {
+                cla_parser_error!("lint: Expected FILE...");
+                ::std::process::exit(1);
+            }
+        },
+        Command::Lsp => {
+            if !result.input_file_names.is_empty() {
+                cla_parser_error!("lsp: Unexpected FILE");
+                ::std::process::exit(1);
+            }
+        },
+        Command::Get { ref mut key } => {
+            if result.input_file_names.len() < 2 {
+                cla_parser_error!("get: Expected FILE... KEY");
+                ::std::process::exit(1);
+            }
+            let text = result.input_file_names.pop().unwrap();
+            *key = match parse_key_path(&text) {
+                Some(path) => path,
+                None => {
+                    cla_parser_error!("Invalid key: \"{}\"", text);
+                    ::std::process::exit(1);
+                },
+            };
+        },
+        Command::Set { ref mut key, ref mut value } => {
+            if result.input_file_names.len() != 3 {
+                cla_parser_error!("set: Expected FILE KEY VALUE");
+                ::std::process::exit(1);
+            }
+            *value = result.input_file_names.pop().unwrap();
+            let text = result.input_file_names.pop().unwrap();
+            *key = match parse_key_path(&text) {
+                Some(path) => path,
+                None => {
+                    cla_parser_error!("Invalid key: \"{}\"", text);
+                    ::std::process::exit(1);
+                },
+            };
+        },
+    }
     return result;
 }
 
@@ -342,7 +842,12 @@ fn handle_cla(program_name: String, config: Config) {
         ::std::process::exit(0);
     }
 
-    if config.input_file_name.is_none() {
+    let needs_file = match config.command {
+        Command::Lsp => false,
+        Command::JsonSchema => config.schema_file_name.is_none(),
+        _ => true,
+    };
+    if config.input_file_names.is_empty() && needs_file {
         cla_handler_error!("No file name provided");
         ::std::process::exit(1);
     }
@@ -355,9 +860,10 @@ struct Loc {
     column_number: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Number {
     USIZE { value: usize },
+    FLOAT { value: f64 },
 }
 
 impl ToString for Number {
@@ -366,6 +872,10 @@ impl ToString for Number {
             Number::USIZE { value } => {
                 value.to_string()
             },
+            // `{:?}` keeps the `.0` of whole numbers
+            Number::FLOAT { value } => {
+                format!("{:?}", value)
+            },
         }
     }
 }
@@ -377,13 +887,16 @@ enum TokValue {
     #[allow(non_camel_case_types)] BOOLEAN { value: bool },
     #[allow(non_camel_case_types)] SPECCHAR { value: String },
     #[allow(non_camel_case_types)] STRING { value: String, quote_type: char },
-    #[allow(non_camel_case_types)] ONE_LINE_COMMENT,
+    #[allow(non_camel_case_types)] ONE_LINE_COMMENT { value: String },
+    #[allow(non_camel_case_types)] REFERENCE { value: String },
 }
 
 #[derive(Debug)]
 struct Tok {
     loc: Loc,
     value: TokValue,
+    // Byte offsets of the token in the source, comments and quotes included
+    span: ::std::ops::Range<usize>,
 }
 
 fn str_to_usize(string: &str) -> Option<usize> {
@@ -408,6 +921,21 @@ fn str_to_usize(string: &str) -> Option<usize> {
 }
 
 fn str_to_num(string: &str) -> Option<Number> {
+    if let Some((whole, fraction)) = string.split_once('.') {
+        // The whole part may not fit a usize
+        let digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+        if !digits(whole) || !digits(fraction) {
+            return None;
+        }
+        // Too many digits parse as infinity
+        let value: f64 = string.parse().ok()?;
+        if !value.is_finite() {
+            return None;
+        }
+        return Some(Number::FLOAT {
+            value,
+        });
+    }
     let value = match str_to_usize(string) {
         Some(value) => value,
         None => {
@@ -419,7 +947,116 @@ fn str_to_num(string: &str) -> Option<Number> {
     });
 }
 
+// Reads FILE, or standard input when FILE is `-`
+fn read_input(file_name: &str) -> ::std::io::Result<String> {
+    if file_name == "-" {
+        return ::std::io::read_to_string(::std::io::stdin());
+    }
+    return ::std::fs::read_to_string(file_name);
+}
+
+// Writes to a temporary file next to `file_name` and renames it over
+// `file_name`, so readers never see a partially written file
+fn write_file_atomically(file_name: &str, contents: &str) -> ::std::io::Result<()> {
+    use ::std::io::Write;
+    let path = ::std::path::Path::new(file_name);
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => ::std::path::Path::new("."),
+    };
+    let base_name = match path.file_name() {
+        Some(base_name) => base_name.to_string_lossy().to_string(),
+        None => {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, "not a file name"));
+        },
+    };
+    let temporary = directory.join(format!(".{}.{}.tmp", base_name, ::std::process::id()));
+    let result = (|| {
+        let mut file = ::std::fs::File::create(&temporary)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        ::std::fs::rename(&temporary, path)
+    })();
+    if result.is_err() {
+        let _ = ::std::fs::remove_file(&temporary);
+    }
+    return result;
+}
+
+// Writes `output` to the -o file, or else to `default_file_name`,
+// or else to stdout; `-` also means stdout
+fn write_output(config: &Config, default_file_name: Option<&str>, output: &str) {
+    let output_file_name = match (&config.output_file_name, default_file_name) {
+        (Some(output_file_name), _) => output_file_name.as_str(),
+        (None, Some(default_file_name)) => default_file_name,
+        (None, None) => "-",
+    };
+    if output_file_name == "-" {
+        print!("{}", output);
+        return;
+    }
+    if let Err(e) = write_file_atomically(output_file_name, output) {
+        error!("Cannot write file: {}: {}", output_file_name, e);
+        ::std::process::exit(2);
+    }
+}
+
+// Escapes `file_name` for a Makefile rule
+fn make_escape(file_name: &str) -> String {
+    let mut result = String::new();
+    for c in file_name.chars() {
+        match c {
+            ' ' | '\t' | '#' => {
+                result.push('\\');
+                result.push(c);
+            },
+            '$' => result += "$$",
+            _ => result.push(c),
+        }
+    }
+    return result;
+}
+
+// Make rule saying that `target` depends on `files`, plus an empty
+// rule for each file so that deleting one does not break the build
+fn make_rule(target: &str, files: &[String]) -> String {
+    let mut result = format!("{}:", make_escape(target));
+    for file_name in files {
+        result += &format!(" \\\n    {}", make_escape(file_name));
+    }
+    result.push('\n');
+    for file_name in files {
+        result += &format!("\n{}:\n", make_escape(file_name));
+    }
+    return result;
+}
+
+// Name of `input_file_name` in messages
+fn display_file_name(config: &Config, input_file_name: &str) -> String {
+    return match config.display_file_name.clone() {
+        Some(filename) => filename,
+        None if input_file_name == "-" => String::from("<stdin>"),
+        None => input_file_name.to_string(),
+    };
+}
+
 fn lex(program_name: String, config: Config) -> Vec<Tok> {
+    let source = match config.input_file_name.as_deref().map(read_input) {
+        Some(Ok(string)) => string,
+        Some(Err(e)) => {
+            error!("lexer: Cannot read file: {}", e);
+            ::std::process::exit(2);
+        },
+        None => {
+            error!("lexer: No file name provided!");
+            ::std::process::exit(1);
+        },
+    };
+    return lex_source(program_name, config, source);
+}
+
+// Splits `source`, the contents of `config.input_file_name`, into tokens
+fn lex_source(program_name: String, config: Config, source: String) -> Vec<Tok> {
     #[allow(unused_macros)]
     macro_rules! lexer_error {
         ($($msg: expr),+) => {
@@ -457,45 +1094,45 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
         NOP,
         WHITESPACE,
         ONELINECOMMENT,
+        REFERENCE { opened: bool },
     }
     let mut state = State::IDENTIFIER;
     let mut result = Vec::new();
-    let input = match config.input_file_name {
+    let input = match config.input_file_name.clone() {
         Some(filename) => filename,
         None => {
             lexer_error!("No file name provided!");
             ::std::process::exit(1);
         },
     };
+    let filename = display_file_name(&config, &input);
     let mut loc = Loc {
-        filename: input.clone(),
+        filename: filename.clone(),
         line_number: config.starting_line_number,
         column_number: config.starting_column_number - 1,
     };
     let mut prev_loc = Loc {
-        filename: input.clone(),
+        filename,
         line_number: config.starting_line_number,
         column_number: config.starting_column_number,
     };
     let mut current_text = String::new();
-    let input = match ::std::fs::read_to_string(input) {
-        Ok(string) => string,
-        Err(e) => {
-            lexer_error!("Cannot read file: {}", e);
-            ::std::process::exit(2);
-        }
-    };
+    let input = source;
+    // The token starts at byte `tok_start` of `input` and ends before byte `$end`
+    let mut tok_start: usize = 0;
     macro_rules! add_tok {
-        () => {
+        ($end: expr) => {
             match state {
                 State::NOP => {
                     None
                 },
-                State::ONELINECOMMENT => {
-                    None
-                },
                 _ => {
                     let value = match state {
+                        State::ONELINECOMMENT => {
+                            TokValue::ONE_LINE_COMMENT {
+                                value: current_text.clone(),
+                            }
+                        },
                         State::SPECCHAR => {
                             TokValue::SPECCHAR {
                                 value: current_text.clone(),
@@ -539,19 +1176,35 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
                                 quote_type,
                             }
                         },
+                        State::REFERENCE { .. } => {
+                            TokValue::REFERENCE {
+                                value: current_text.clone(),
+                            }
+                        },
                         _ => unreachable!(),
                     };
                     Some(Tok {
                         loc: prev_loc.clone(),
                         value,
+                        span: tok_start..$end,
                     })
                 },
             }
         };
     }
-    for c in input.chars() {
+    for (offset, c) in input.char_indices() {
         match state {
             State::STRING { quote_type, escaping } => {
+                if false
+                    || c == '\n'
+                    || c == '\x0B'
+                    || c == '\x0C'
+                {
+                    loc.line_number += 1;
+                    loc.column_number = config.starting_column_number - 1;
+                } else {
+                    loc.column_number += 1;
+                }
                 if escaping {
                     let c = match c {
                         '\\' => '\\',
@@ -580,8 +1233,7 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
                 }
                 if c == quote_type {
                     lexer_loc_warning!(loc, "current_text: {} ({}: {})", current_text, prev_loc.line_number, prev_loc.column_number);
-                    prev_loc = loc.clone();
-                    let tok = add_tok!();
+                    let tok = add_tok!(offset + c.len_utf8());
                     state = State::NOP;
                     current_text = String::new();
                     match tok {
@@ -595,18 +1247,66 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
                 current_text.push(c);
                 continue;
             },
-            _ => {}
-        }
-        if false
-            || c == '\n'
-            || c == '\x0B'
-            || c == '\x0C'
+            State::REFERENCE { opened } => {
+                loc.column_number += 1;
+                if !opened {
+                    if c != '{' {
+                        lexer_loc_error!(loc, "Expected `{{` after `$`");
+                        ::std::process::exit(3);
+                    }
+                    state = State::REFERENCE { opened: true };
+                    continue;
+                }
+                if c == '\n' {
+                    lexer_loc_error!(loc, "Unterminated reference");
+                    ::std::process::exit(3);
+                }
+                if c == '}' {
+                    let tok = add_tok!(offset + c.len_utf8());
+                    state = State::NOP;
+                    current_text = String::new();
+                    match tok {
+                        Some(tok) => {
+                            result.push(tok);
+                        },
+                        None => {},
+                    }
+                    continue;
+                }
+                current_text.push(c);
+                continue;
+            },
+            State::ONELINECOMMENT => {
+                if !(false
+                    || c == '\n'
+                    || c == '\x0B'
+                    || c == '\x0C')
+                {
+                    current_text.push(c);
+                    continue;
+                }
+                let tok = add_tok!(offset);
+                state = State::NOP;
+                current_text = String::new();
+                match tok {
+                    Some(tok) => {
+                        result.push(tok);
+                    },
+                    None => {},
+                }
+            },
+            _ => {}
+        }
+        if false
+            || c == '\n'
+            || c == '\x0B'
+            || c == '\x0C'
         {
             loc.line_number += 1;
             loc.column_number = config.starting_column_number - 1;
             if !matches!(state, State::NOP) && !current_text.is_empty() {
                 eprintln!("{}: {}: ({}: {}): adding newline: \"{}\"", loc.line_number, loc.column_number, prev_loc.line_number, prev_loc.column_number, current_text);
-                let tok = add_tok!();
+                let tok = add_tok!(offset);
                 prev_loc.column_number += 1;
                 current_text = String::new();
                 match tok {
@@ -628,20 +1328,14 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
         }
         loc.column_number += 1;
         let ciad = c.is_ascii_digit();
-        if false
-            || c.is_lowercase()
-            || c.is_uppercase()
-            || ciad
-            || c == '-'
-            || c == '_'
-        {
+        if is_word_char(c) {
             match state {
                 State::IDENTIFIER => {},
                 State::NUMBER => {},
                 _ => {
                     if !current_text.is_empty() {
                         eprintln!("{}: {}: ({}: {}): adding identifier: \"{}\"", loc.line_number, loc.column_number, prev_loc.line_number, prev_loc.column_number, current_text);
-                        let tok = add_tok!();
+                        let tok = add_tok!(offset);
                         current_text = String::new();
                         match tok {
                             Some(tok) => {
@@ -658,13 +1352,16 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
             } else {
                 state = State::IDENTIFIER;
             }
+            if current_text.is_empty() {
+                tok_start = offset;
+            }
             current_text.push(c);
             continue;
         }
         if c.is_whitespace() {
             if !current_text.is_empty() {
                 eprintln!("{}: {}: ({}: {}): adding whitespace: \"{}\"", loc.line_number, loc.column_number, prev_loc.line_number, prev_loc.column_number, current_text);
-                let tok = add_tok!();
+                let tok = add_tok!(offset);
                 current_text = String::new();
                 match tok {
                     Some(tok) => {
@@ -677,6 +1374,28 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
             state = State::WHITESPACE;
             continue;
         }
+        if c == '$' || c == '#' {
+            if !current_text.is_empty() {
+                let tok = add_tok!(offset);
+                current_text = String::new();
+                match tok {
+                    Some(tok) => {
+                        result.push(tok);
+                    }
+                    None => {},
+                }
+            }
+            prev_loc = loc.clone();
+            tok_start = offset;
+            state = if c == '$' {
+                State::REFERENCE {
+                    opened: false,
+                }
+            } else {
+                State::ONELINECOMMENT
+            };
+            continue;
+        }
         if false
             || c == '\''
             || c == '"'
@@ -684,8 +1403,7 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
         {
             if !current_text.is_empty() {
                 eprintln!("{}: {}: ({}: {}): adding string: \"{}\"", loc.line_number, loc.column_number, prev_loc.line_number, prev_loc.column_number, current_text);
-                let tok = add_tok!();
-                prev_loc = loc.clone();
+                let tok = add_tok!(offset);
                 current_text = String::new();
                 match tok {
                     Some(tok) => {
@@ -694,17 +1412,29 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
                     None => {},
                 }
             }
+            prev_loc = loc.clone();
+            tok_start = offset;
             state = State::STRING {
                 quote_type: c,
                 escaping: false,
             };
             continue;
         }
-        eprintln!("{}: {}: ({}: {}): adding specchar: \"{}\"", loc.line_number, loc.column_number, prev_loc.line_number, prev_loc.column_number, current_text);
-        let mut tok = add_tok!();
-        if current_text.is_empty() {
-            tok = None;
+        // `0.5` is one number, a `.` between keys is a specchar
+        if c == '.'
+            && matches!(state, State::NUMBER)
+            && !current_text.contains('.')
+            && input[offset + 1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            current_text.push(c);
+            continue;
         }
+        eprintln!("{}: {}: ({}: {}): adding specchar: \"{}\"", loc.line_number, loc.column_number, prev_loc.line_number, prev_loc.column_number, current_text);
+        let tok = if current_text.is_empty() {
+            None
+        } else {
+            add_tok!(offset)
+        };
         current_text = String::new();
         match tok {
             Some(tok) => {
@@ -713,18 +1443,91 @@ fn lex(program_name: String, config: Config) -> Vec<Tok> {
             None => {},
         }
         state = State::SPECCHAR;
+        tok_start = offset;
         current_text.push(c);
         prev_loc = loc.clone();
     }
+    match state {
+        State::STRING { .. } => {
+            lexer_loc_error!(prev_loc, "Unterminated string");
+            ::std::process::exit(3);
+        },
+        State::REFERENCE { .. } => {
+            lexer_loc_error!(prev_loc, "Unterminated reference");
+            ::std::process::exit(3);
+        },
+        _ => {
+            if !current_text.is_empty() || matches!(state, State::ONELINECOMMENT) {
+                let tok = add_tok!(input.len());
+                match tok {
+                    Some(tok) => {
+                        result.push(tok);
+                    },
+                    None => {},
+                }
+            }
+        },
+    }
     return result;
 }
 
-#[derive(Debug)]
+// What `${...}` refers to
+#[derive(Debug, Clone)]
+enum Reference {
+    Key(Vec<String>),
+    // `${env:NAME}` or `${env:NAME:-DEFAULT}`
+    Env { name: String, default: Option<String> },
+    // `${arg:N}`, the N-th argument after `--`
+    Argument { index: usize, default: Option<String> },
+    // `${param:KEY}`, set with `-D KEY=VALUE`
+    Parameter { key: String, default: Option<String> },
+}
+
+impl ToString for Reference {
+    fn to_string(&self) -> String {
+        match self {
+            Reference::Key(path) => {
+                path.join(".")
+            },
+            Reference::Env { name, default } => {
+                match default {
+                    Some(default) => format!("env:{}:-{}", name, default),
+                    None => format!("env:{}", name),
+                }
+            },
+            Reference::Argument { index, default } => {
+                match default {
+                    Some(default) => format!("arg:{}:-{}", index, default),
+                    None => format!("arg:{}", index),
+                }
+            },
+            Reference::Parameter { key, default } => {
+                match default {
+                    Some(default) => format!("param:{}:-{}", key, default),
+                    None => format!("param:{}", key),
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum TemplatePart {
+    Text(String),
+    Reference(Reference),
+}
+
+#[derive(Debug, Clone)]
 enum Item {
     Text(String),
     Number(Number),
     Boolean(bool),
     Item(Box<Item>),
+    Table(::std::collections::HashMap<String, Item>),
+    List(Vec<Item>),
+    // Resolved by `resolve_references` once all files are merged
+    Reference { reference: Reference, loc: Loc },
+    Template { parts: Vec<TemplatePart>, loc: Loc },
 }
 
 impl ToString for Item {
@@ -740,11 +1543,392 @@ impl ToString for Item {
             Item::Item(item) => {
                 item.to_string()
             },
+            Item::Table(table) => {
+                let items: Vec<String> = table.iter().map(|(key, item)| format!("{}: {}", key, item.to_string())).collect();
+                format!("{{{}}}", items.join(", "))
+            },
+            Item::List(list) => {
+                let items: Vec<String> = list.iter().map(|item| item.to_string()).collect();
+                format!("[{}]", items.join(", "))
+            },
+            Item::Reference { reference, .. } => {
+                format!("${{{}}}", reference.to_string())
+            },
+            Item::Template { parts, .. } => {
+                let mut result = String::new();
+                for part in parts {
+                    match part {
+                        TemplatePart::Text(text) => {
+                            result += &text.replace("${", "$${");
+                        },
+                        TemplatePart::Reference(reference) => {
+                            result += &format!("${{{}}}", reference.to_string());
+                        },
+                    }
+                }
+                result
+            },
+        }
+    }
+}
+
+// Characters the lexer reads as part of an identifier or a number
+fn is_word_char(c: char) -> bool {
+    return c.is_lowercase() || c.is_uppercase() || c.is_ascii_digit() || c == '-' || c == '_';
+}
+
+// Whether the lexer reads `key` back as an identifier: a word ending
+// in a digit is read as a number, and keywords mean something else
+fn is_bare_key(key: &str) -> bool {
+    match (key.chars().next(), key.chars().last()) {
+        (Some(first), Some(last)) if (first.is_lowercase() || first.is_uppercase() || first == '_') && !last.is_ascii_digit() => {},
+        _ => return false,
+    }
+    return key.chars().all(is_word_char) && !matches!(key, "include" | "unset" | "true" | "false");
+}
+
+// `key` as it is written in a smartconf file, quoted unless it is bare
+fn key_to_string(key: &str) -> String {
+    if is_bare_key(key) {
+        return key.to_string();
+    }
+    let quote_type = if key.contains('\'') && !key.contains('"') {
+        '"'
+    } else {
+        '\''
+    };
+    return smartconf_repr(key, quote_type);
+}
+
+// `path` as it is written in a smartconf file
+fn key_path_to_string(path: &[String]) -> String {
+    let keys: Vec<String> = path.iter().map(|key| key_to_string(key)).collect();
+    return keys.join(".");
+}
+
+// Where the current value of each key was defined,
+// and which files were read to generate it
+#[derive(Debug, Default)]
+struct Provenance {
+    keys: ::std::collections::HashMap<Vec<String>, Loc>,
+    // Input files and the files they include, in the order they were read
+    files: Vec<String>,
+    // Type annotations of keys whose value is a reference, checked
+    // once references are resolved
+    types: ::std::collections::HashMap<Vec<String>, (schema::Type, Loc)>,
+}
+
+impl Provenance {
+    // Records that `file_name` is about to be read. With --list-files,
+    // the name is written right away, so that the list is complete up
+    // to the file with an error when generating fails.
+    fn read_file(&mut self, config: &Config, file_name: &str) {
+        if file_name == "-" || self.files.iter().any(|read| read == file_name) {
+            return;
+        }
+        self.files.push(file_name.to_string());
+        if let Some(ref list_files) = config.list_files {
+            let written = ::std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(list_files)
+                .and_then(|mut list| {
+                    use ::std::io::Write;
+                    writeln!(list, "{}", file_name)
+                });
+            if let Err(e) = written {
+                error!("Cannot write file: {}: {}", list_files, e);
+                ::std::process::exit(2);
+            }
+        }
+    }
+
+    fn forget(&mut self, path: &[String]) {
+        self.keys.retain(|key, _| !key.starts_with(path));
+        self.types.retain(|key, _| !key.starts_with(path));
+    }
+}
+
+// Splits `a.'b c'.d` into its keys
+fn parse_key_path(text: &str) -> Option<Vec<String>> {
+    let mut path = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut quoted = false;
+    for c in text.trim().chars() {
+        match quote {
+            Some(quote_type) => {
+                if c == quote_type {
+                    quote = None;
+                } else {
+                    current.push(c);
+                }
+            },
+            None => {
+                match c {
+                    '\'' | '"' | '`' => {
+                        quote = Some(c);
+                        quoted = true;
+                    },
+                    '.' => {
+                        if current.is_empty() && !quoted {
+                            return None;
+                        }
+                        path.push(::std::mem::take(&mut current));
+                        quoted = false;
+                    },
+                    _ => {
+                        if c.is_whitespace() {
+                            return None;
+                        }
+                        current.push(c);
+                    },
+                }
+            },
+        }
+    }
+    if quote.is_some() || (current.is_empty() && !quoted) {
+        return None;
+    }
+    path.push(current);
+    return Some(path);
+}
+
+fn parse_reference(text: &str) -> Option<Reference> {
+    // `NAME:-DEFAULT`
+    fn split_default(text: &str) -> Option<(String, Option<String>)> {
+        let (name, default) = match text.split_once(":-") {
+            Some((name, default)) => (name, Some(default.to_string())),
+            None => (text, None),
+        };
+        if name.is_empty() || name.chars().any(|c| c.is_whitespace()) {
+            return None;
+        }
+        return Some((name.to_string(), default));
+    }
+    if let Some(env) = text.strip_prefix("env:") {
+        let (name, default) = split_default(env)?;
+        return Some(Reference::Env { name, default });
+    }
+    if let Some(argument) = text.strip_prefix("arg:") {
+        let (index, default) = split_default(argument)?;
+        return match str_to_usize(&index) {
+            Some(index) if index != 0 => Some(Reference::Argument { index, default }),
+            _ => None,
+        };
+    }
+    if let Some(parameter) = text.strip_prefix("param:") {
+        let (key, default) = split_default(parameter)?;
+        return Some(Reference::Parameter { key, default });
+    }
+    return parse_key_path(text).map(Reference::Key);
+}
+
+// Values given on the command line are typed like in the files
+fn parse_parameter(value: &str) -> Item {
+    match value {
+        "true" => Item::Boolean(true),
+        "false" => Item::Boolean(false),
+        _ => {
+            match str_to_num(value) {
+                Some(number) if !value.is_empty() => Item::Number(number),
+                _ => Item::Text(value.to_string()),
+            }
+        },
+    }
+}
+
+// Splits a string value into text and `${...}` references.
+// `$${` stands for a literal `${`.
+fn parse_template(text: &str) -> Result<Vec<TemplatePart>, String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if rest.starts_with("$${") {
+            current += "${";
+            rest = &rest[3..];
+            continue;
+        }
+        if rest.starts_with("${") {
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => return Err(String::from("Unterminated reference")),
+            };
+            let reference = match parse_reference(&rest[2..end]) {
+                Some(reference) => reference,
+                None => return Err(format!("Invalid reference: `{}`", &rest[..=end])),
+            };
+            if !current.is_empty() {
+                parts.push(TemplatePart::Text(::std::mem::take(&mut current)));
+            }
+            parts.push(TemplatePart::Reference(reference));
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        current.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if !current.is_empty() {
+        parts.push(TemplatePart::Text(current));
+    }
+    return Ok(parts);
+}
+
+// How a newly defined value is combined with the value
+// that is already stored under the same key
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MergeStrategy {
+    // Tables are merged recursively, everything else is replaced
+    Merge,
+    Replace,
+    Append,
+    Prepend,
+}
+
+fn merge_items(old: Item, new: Item, strategy: MergeStrategy) -> Item {
+    match (old, new, strategy) {
+        (_, new, MergeStrategy::Replace) => new,
+        (Item::Table(mut old), Item::Table(new), _) => {
+            for (key, item) in new {
+                let item = match old.remove(&key) {
+                    Some(old_item) => merge_items(old_item, item, MergeStrategy::Merge),
+                    None => item,
+                };
+                old.insert(key, item);
+            }
+            Item::Table(old)
+        },
+        (Item::List(mut old), Item::List(new), MergeStrategy::Append) => {
+            old.extend(new);
+            Item::List(old)
+        },
+        (Item::List(old), Item::List(mut new), MergeStrategy::Prepend) => {
+            new.extend(old);
+            Item::List(new)
+        },
+        (_, new, _) => new,
+    }
+}
+
+// Removes the item at `path`, descending into nested tables
+fn remove_path(table: &mut ::std::collections::HashMap<String, Item>, path: &[String]) -> Option<Item> {
+    match path {
+        [] => None,
+        [key] => table.remove(key),
+        [key, rest @ ..] => {
+            match table.get_mut(key) {
+                Some(Item::Table(table)) => remove_path(table, rest),
+                _ => None,
+            }
+        },
+    }
+}
+
+// Returns the table at `path`, creating missing tables on the way.
+// Fails with the conflicting key when something else is in the way.
+fn table_at_path<'a>(table: &'a mut ::std::collections::HashMap<String, Item>, path: &[String]) -> Result<&'a mut ::std::collections::HashMap<String, Item>, String> {
+    let mut table = table;
+    for (index, key) in path.iter().enumerate() {
+        table = match table.entry(key.clone()).or_insert_with(|| Item::Table(::std::collections::HashMap::new())) {
+            Item::Table(table) => table,
+            _ => return Err(path[..=index].join(".")),
+        };
+    }
+    return Ok(table);
+}
+
+// Item at `path`, descending into nested tables
+fn get_path<'a>(table: &'a ::std::collections::HashMap<String, Item>, path: &[String]) -> Option<&'a Item> {
+    match path {
+        [] => None,
+        [key] => table.get(key),
+        [key, rest @ ..] => {
+            match table.get(key) {
+                Some(Item::Table(table)) => get_path(table, rest),
+                _ => None,
+            }
+        },
+    }
+}
+
+// Evaluates the file of `config` on top of `base`, which is at `prefix`
+// in the result, as smartconf or through `import`
+fn generate_file_layer(program_name: String, config: Config, base: ::std::collections::HashMap<String, Item>, prefix: Vec<String>, provenance: &mut Provenance) -> ::std::collections::HashMap<String, Item> {
+    let file_name = config.input_file_name.clone().unwrap();
+    provenance.read_file(&config, &file_name);
+    match config.input_format.unwrap_or_else(|| InputFormat::of_file(&file_name)) {
+        InputFormat::Smartconf => {
+            let lexed = lex(program_name.clone(), config.clone());
+            return generate_hashmap_layer(program_name, lexed, config, base, prefix, provenance);
+        },
+        input_format => {
+            return import::layer(&config, input_format, base, &prefix, provenance);
+        },
+    }
+}
+
+// Merges all input files in order and resolves the result
+fn generate_hashmap(program_name: String, config: Config, provenance: &mut Provenance) -> ::std::collections::HashMap<String, Item> {
+    let mut hashmap = ::std::collections::HashMap::new();
+    if let Some(ref list_files) = config.list_files {
+        if let Err(e) = ::std::fs::write(list_files, "") {
+            error!("Cannot write file: {}: {}", list_files, e);
+            ::std::process::exit(2);
+        }
+    }
+    for (index, input_file_name) in config.input_file_names.iter().enumerate() {
+        let mut config = Config {
+            input_file_name: Some(input_file_name.to_string()),
+            ..config.clone()
+        };
+        if index != 0 {
+            config.starting_line_number = 1;
+            config.starting_column_number = 1;
+            config.display_file_name = None;
+        }
+        hashmap = generate_file_layer(program_name.clone(), config, hashmap, Vec::new(), provenance);
+    }
+    for (key, value) in &config.parameters {
+        let path = parse_key_path(key).unwrap();
+        let (key, parents) = path.split_last().unwrap();
+        match table_at_path(&mut hashmap, parents) {
+            Ok(table) => {
+                table.insert(key.to_string(), parse_parameter(value));
+            },
+            Err(conflict) => {
+                error!("generator: Cannot define `{}`: `{}` is not a table", key_path_to_string(&path), conflict);
+                ::std::process::exit(3);
+            },
+        }
+        provenance.forget(&path);
+        provenance.keys.insert(path, Loc {
+            filename: String::from("<command line>"),
+            line_number: 1,
+            column_number: 1,
+        });
+    }
+    let hashmap = resolve_references(hashmap, &config);
+    let mut types: Vec<(&Vec<String>, &(schema::Type, Loc))> = provenance.types.iter().collect();
+    types.sort_by_key(|(_, (_, loc))| (loc.filename.clone(), loc.line_number, loc.column_number));
+    for (path, (kind, loc)) in types {
+        let found = match get_path(&hashmap, path) {
+            Some(item) => schema::Type::of(item),
+            None => continue,
+        };
+        if !kind.accepts(found) {
+            error!("resolver: {}: {}: {}: `{}` should be {} {}, not {} {}", loc.filename, loc.line_number, loc.column_number, key_path_to_string(path), schema::article(*kind), kind.name(), schema::article(found), found.name());
+            ::std::process::exit(3);
         }
     }
+    return hashmap;
 }
 
-fn generate_hashmap(program_name: String, lexed: Vec<Tok>, config: Config) -> ::std::collections::HashMap<String, Item> {
+// Evaluates `lexed` on top of `base`, so that every definition
+// is merged with what earlier files (or includes) have defined.
+// `prefix` is the path of `base` in the result.
+fn generate_hashmap_layer(program_name: String, lexed: Vec<Tok>, config: Config, base: ::std::collections::HashMap<String, Item>, prefix: Vec<String>, provenance: &mut Provenance) -> ::std::collections::HashMap<String, Item> {
     #[allow(unused_macros)]
     macro_rules! generator_error {
         ($($msg: expr),+) => {
@@ -776,228 +1960,845 @@ fn generate_hashmap(program_name: String, lexed: Vec<Tok>, config: Config) -> ::
     #[derive(Debug, Clone)]
     enum State {
         #[allow(non_camel_case_types)] KEY,
-        #[allow(non_camel_case_types)] COLON { key: String },
-        #[allow(non_camel_case_types)] VALUE { key: String },
-        #[allow(non_camel_case_types)] ONE_LINE_COMMENT { line_number: usize, previous_state: Box<State> },
-        #[allow(non_camel_case_types)] INCLUDE,
+        #[allow(non_camel_case_types)] COLON { path: Vec<String> },
+        #[allow(non_camel_case_types)] KEY_DOT { path: Vec<String> },
+        #[allow(non_camel_case_types)] VALUE { path: Vec<String>, strategy: MergeStrategy, kind: Option<schema::Type> },
+        #[allow(non_camel_case_types)] TYPE_EQUALS { path: Vec<String>, kind: schema::Type },
+        #[allow(non_camel_case_types)] MERGE_STRATEGY { path: Vec<String>, kind: Option<schema::Type> },
+        #[allow(non_camel_case_types)] LIST_VALUE,
+        #[allow(non_camel_case_types)] LIST_COMMA,
+        #[allow(non_camel_case_types)] INCLUDE { format: Option<InputFormat> },
+        #[allow(non_camel_case_types)] UNSET { path: Vec<String> },
+    }
+    // A table or a list whose closing bracket was not reached yet.
+    // `path` is None when the frame is an element of a list,
+    // and is the prefix of the whole layer for the first frame.
+    #[derive(Debug)]
+    enum Frame {
+        Table { path: Option<Vec<String>>, table: ::std::collections::HashMap<String, Item> },
+        List { path: Option<Vec<String>>, strategy: MergeStrategy, list: Vec<Item> },
     }
     let mut state = State::KEY;
+    let mut stack = vec![Frame::Table { path: Some(prefix), table: base }];
     if config.input_file_name.is_none() {
         generator_error!("No file name provided!");
         ::std::process::exit(1);
     }
-    let input_file = match ::std::fs::read_to_string(config.input_file_name.clone().unwrap()) {
-        Ok(text) => text,
-        Err(e) => {
-            generator_error!("Cannot read file: {}", e);
-            ::std::process::exit(2);
-        }
-    };
-    let mut result = ::std::collections::HashMap::<String, Item>::new();
     eprintln!("{:#?}", lexed);
-    for tok in lexed {
-        eprintln!("{}: {}: {}: \"{:?}\"", tok.loc.filename, tok.loc.line_number, tok.loc.column_number, tok.value);
-        match state.clone() {
-            State::ONE_LINE_COMMENT { line_number, previous_state } => {
-                if tok.loc.line_number != line_number {
-                    state = *previous_state;
-                } else {
-                    continue;
+    // Path of `$path` from the root of the result,
+    // None when it is inside a list
+    macro_rules! full_path {
+        ($path: expr) => {
+            {
+                let mut full_path = Some(Vec::new());
+                for frame in &stack {
+                    match (frame, &mut full_path) {
+                        (Frame::Table { path: Some(path), .. }, Some(full_path)) => {
+                            full_path.extend(path.iter().cloned());
+                        },
+                        _ => {
+                            full_path = None;
+                        },
+                    }
                 }
-            },
-            _ => {},
-        }
-        let specchar_good: bool;
-        match tok.value {
-            TokValue::SPECCHAR { ref value } => {
-                match value.as_str() {
-                    "#" => {
-                        state = State::ONE_LINE_COMMENT {
-                            line_number: tok.loc.line_number,
-                            previous_state: Box::new(state),
-                        };
-                        specchar_good = false;
-                    },
-                    _ => {
-                        specchar_good = true;
-
-                    },
+                full_path.map(|mut full_path: Vec<String>| {
+                    full_path.extend($path.iter().cloned());
+                    full_path
+                })
+            }
+        };
+    }
+    macro_rules! record {
+        ($tok: expr, $path: expr, $forget: expr) => {
+            if let Some(full_path) = full_path!($path) {
+                if $forget {
+                    provenance.forget(&full_path);
                 }
-            },
-            _ => {
-                specchar_good = true;
+                provenance.keys.insert(full_path, $tok.loc.clone());
             }
-        }
-        if specchar_good {
-            match state {
-                State::KEY => {
-                    match tok.value {
-                        TokValue::IDENTIFIER {
-                            value,
-                        } => {
-                            match value.as_str() {
-                                "include" => {
-                                    state = State::INCLUDE;
-                                },
-                                _ => {
-                                    state = State::COLON { key: value };
-                                },
-                            }
-                        }
-                        _ => {
-                            generator_loc_error!(tok, "Expected identifier");
+        };
+    }
+    macro_rules! store {
+        ($tok: expr, $path: expr, $strategy: expr, $item: expr) => {
+            let item: Item = $item;
+            let path: Option<Vec<String>> = $path;
+            if let (Frame::Table { .. }, Some(path)) = (stack.last().unwrap(), &path) {
+                if !matches!(item, Item::Table(_) | Item::List(_)) {
+                    record!($tok, path, true);
+                }
+            }
+            match stack.last_mut().unwrap() {
+                Frame::Table { table, .. } => {
+                    let path: Vec<String> = match path {
+                        Some(path) => path,
+                        None => unreachable!(),
+                    };
+                    let (key, parents) = path.split_last().unwrap();
+                    let table = match table_at_path(table, parents) {
+                        Ok(table) => table,
+                        Err(conflict) => {
+                            generator_loc_error!($tok, "Cannot define `{}`: `{}` is not a table", key_path_to_string(&path), conflict);
                             ::std::process::exit(3);
-                        }
-                    }
-                },
-                State::COLON { key } => {
-                    match tok.value {
-                        TokValue::SPECCHAR {
-                            value: specchar
-                        } => {
-                            if specchar == ":" {
-                                state = State::VALUE { key };
-                                continue;
+                        },
+                    };
+                    let key = key.to_string();
+                    let item = match table.remove(&key) {
+                        Some(old) => {
+                            if matches!($strategy, MergeStrategy::Append | MergeStrategy::Prepend) && !matches!(old, Item::List(_)) {
+                                generator_loc_warning!($tok, "`{}` is not a list, replacing it", key_path_to_string(&path));
                             }
-                            generator_loc_error!(tok, "Expected `:`, found {}", specchar);
-                            ::std::process::exit(3);
-                        }
-                        _ => {
-                            generator_loc_error!(tok, "Expected specchar");
-                            ::std::process::exit(3);
-                        }
-                    }
-                },
-                State::VALUE { ref key } => {
-                    match tok.value {
-                        TokValue::STRING {
-                            value: text,
-                            quote_type: _,
-                        } => {
-                            result.insert(key.to_string(), Item::Text(text));
-                            state = State::KEY;
+                            merge_items(old, item, $strategy)
                         },
-                        TokValue::NUMBER { value } => {
-                            result.insert(key.to_string(), Item::Number(value));
-                            state = State::KEY;
-                        },
-                        TokValue::BOOLEAN { value } => {
-                            result.insert(key.to_string(), Item::Boolean(value));
-                            state = State::KEY;
+                        None => item,
+                    };
+                    table.insert(key, item);
+                    state = State::KEY;
+                },
+                Frame::List { list, .. } => {
+                    list.push(item);
+                    state = State::LIST_COMMA;
+                },
+            }
+        };
+    }
+    macro_rules! string_item {
+        ($tok: expr, $text: expr) => {
+            {
+                let text: String = $text;
+                if text.contains("${") {
+                    match parse_template(&text) {
+                        Ok(parts) => {
+                            if parts.iter().any(|part| matches!(part, TemplatePart::Reference(_))) {
+                                Item::Template { parts, loc: $tok.loc.clone() }
+                            } else {
+                                Item::Text(text.replace("$${", "${"))
+                            }
                         },
-                        _ => {
-                            generator_loc_error!(tok, "Expected string");
+                        Err(e) => {
+                            generator_loc_error!($tok, "{}", e);
                             ::std::process::exit(3);
-                        }
+                        },
                     }
+                } else {
+                    Item::Text(text)
+                }
+            }
+        };
+    }
+    macro_rules! reference_item {
+        ($tok: expr, $text: expr) => {
+            match parse_reference(&$text) {
+                Some(reference) => Item::Reference { reference, loc: $tok.loc.clone() },
+                None => {
+                    generator_loc_error!($tok, "Invalid reference: `${{{}}}`", $text);
+                    ::std::process::exit(3);
                 },
-                State::INCLUDE => {
-                    match tok.value {
-                        TokValue::STRING {
-                            value,
-                            quote_type: _,
-                        } => {
-                            let config = Config {
-                                input_file_name: Some(value),
-                                arguments: config.arguments.clone(),
-                                starting_column_number: config.starting_column_number,
-                                starting_line_number: config.starting_line_number,
-                                format: config.format,
-                                help: config.help,
-                                variable_name: config.variable_name.clone(),
-                            };
-                            let lexed = lex(program_name.clone(), config.clone());
-                            let hashmap = generate_hashmap(program_name.clone(), lexed, config);
-                            result.extend(hashmap.into_iter());
-                            state = State::KEY;
-                        },
-                        _ => {
-                            generator_loc_error!(tok, "Expected string");
+            }
+        };
+    }
+    // Exits unless a value of type `$found` fits the annotation `$kind`
+    macro_rules! check_type {
+        ($tok: expr, $path: expr, $kind: expr, $found: expr) => {
+            if let Some(kind) = $kind {
+                let found: schema::Type = $found;
+                if !kind.accepts(found) {
+                    generator_loc_error!($tok, "`{}` should be {} {}, not {} {}", key_path_to_string($path), schema::article(kind), kind.name(), schema::article(found), found.name());
+                    ::std::process::exit(3);
+                }
+            }
+        };
+    }
+    // `$item` checked against the annotation `$kind`; a `float`
+    // can be written like an int
+    macro_rules! typed {
+        ($tok: expr, $path: expr, $kind: expr, $item: expr) => {
+            match ($kind, $item) {
+                (Some(schema::Type::Float), Item::Number(Number::USIZE { value })) => {
+                    Item::Number(Number::FLOAT { value: value as f64 })
+                },
+                (kind, item) => {
+                    check_type!($tok, $path, kind, schema::Type::of(&item));
+                    item
+                },
+            }
+        };
+    }
+    macro_rules! unset {
+        ($tok: expr, $path: expr) => {
+            let path: Vec<String> = $path;
+            if let Some(full_path) = full_path!(path) {
+                provenance.forget(&full_path);
+            }
+            match stack.last_mut().unwrap() {
+                Frame::Table { table, .. } => {
+                    if remove_path(table, &path).is_none() {
+                        generator_loc_warning!($tok, "Cannot unset `{}`: no such key", key_path_to_string(&path));
+                    }
+                },
+                Frame::List { .. } => unreachable!(),
+            }
+            state = State::KEY;
+        };
+    }
+    macro_rules! open_table {
+        ($tok: expr, $path: expr, $strategy: expr) => {
+            let path: Option<Vec<String>> = $path;
+            if let (Frame::Table { .. }, Some(path)) = (stack.last().unwrap(), &path) {
+                record!($tok, path, $strategy == MergeStrategy::Replace);
+            }
+            let table = match (stack.last_mut().unwrap(), &path) {
+                (Frame::Table { table, .. }, Some(path)) => {
+                    let (key, parents) = path.split_last().unwrap();
+                    let table = match table_at_path(table, parents) {
+                        Ok(table) => table,
+                        Err(conflict) => {
+                            generator_loc_error!($tok, "Cannot define `{}`: `{}` is not a table", key_path_to_string(path), conflict);
                             ::std::process::exit(3);
-                        }
+                        },
+                    };
+                    match table.remove(key) {
+                        Some(Item::Table(old)) if $strategy != MergeStrategy::Replace => old,
+                        Some(old) => {
+                            table.insert(key.clone(), old);
+                            ::std::collections::HashMap::new()
+                        },
+                        None => ::std::collections::HashMap::new(),
                     }
                 },
-                _ => unreachable!(),
+                _ => ::std::collections::HashMap::new(),
+            };
+            stack.push(Frame::Table { path, table });
+            state = State::KEY;
+        };
+    }
+    macro_rules! open_list {
+        ($tok: expr, $path: expr, $strategy: expr) => {
+            let path: Option<Vec<String>> = $path;
+            if let (Frame::Table { .. }, Some(path)) = (stack.last().unwrap(), &path) {
+                record!($tok, path, true);
+            }
+            stack.push(Frame::List { path, strategy: $strategy, list: Vec::new() });
+            state = State::LIST_VALUE;
+        };
+    }
+    macro_rules! close_frame {
+        ($tok: expr) => {
+            match stack.pop().unwrap() {
+                Frame::Table { path, table } => {
+                    store!($tok, path, MergeStrategy::Replace, Item::Table(table));
+                },
+                Frame::List { path, strategy, list } => {
+                    store!($tok, path, strategy, Item::List(list));
+                },
             }
+        };
+    }
+    let mut lexed = lexed.into_iter().peekable();
+    while let Some(tok) = lexed.next() {
+        eprintln!("{}: {}: {}: \"{:?}\"", tok.loc.filename, tok.loc.line_number, tok.loc.column_number, tok.value);
+        if matches!(tok.value, TokValue::ONE_LINE_COMMENT { .. }) {
+            continue;
+        }
+        match state {
+            State::KEY => {
+                match tok.value {
+                    TokValue::IDENTIFIER {
+                        value,
+                    } => {
+                        match value.as_str() {
+                            "include" => {
+                                state = State::INCLUDE { format: None };
+                            },
+                            "unset" => {
+                                state = State::UNSET { path: Vec::new() };
+                            },
+                            _ => {
+                                state = State::COLON { path: vec![value] };
+                            },
+                        }
+                    }
+                    TokValue::STRING {
+                        value,
+                        quote_type: _,
+                    } => {
+                        state = State::COLON { path: vec![value] };
+                    }
+                    TokValue::SPECCHAR {
+                        ref value,
+                    } if value == "}" && stack.len() > 1 => {
+                        close_frame!(tok);
+                    }
+                    _ => {
+                        generator_loc_error!(tok, "Expected identifier");
+                        ::std::process::exit(3);
+                    }
+                }
+            },
+            State::COLON { path } => {
+                match tok.value {
+                    TokValue::SPECCHAR {
+                        value: specchar
+                    } => {
+                        if specchar == ":" {
+                            state = State::VALUE { path, strategy: MergeStrategy::Merge, kind: None };
+                            continue;
+                        }
+                        if specchar == "." {
+                            state = State::KEY_DOT { path };
+                            continue;
+                        }
+                        generator_loc_error!(tok, "Expected `:`, found {}", specchar);
+                        ::std::process::exit(3);
+                    }
+                    _ => {
+                        generator_loc_error!(tok, "Expected specchar");
+                        ::std::process::exit(3);
+                    }
+                }
+            },
+            State::KEY_DOT { mut path } => {
+                match tok.value {
+                    TokValue::IDENTIFIER { value } | TokValue::STRING { value, .. } => {
+                        path.push(value);
+                        state = State::COLON { path };
+                    },
+                    _ => {
+                        generator_loc_error!(tok, "Expected key after `.`");
+                        ::std::process::exit(3);
+                    },
+                }
+            },
+            State::TYPE_EQUALS { path, kind } => {
+                match tok.value {
+                    TokValue::SPECCHAR { ref value } if value == "=" => {
+                        state = State::VALUE { path, strategy: MergeStrategy::Merge, kind: Some(kind) };
+                    },
+                    _ => {
+                        generator_loc_error!(tok, "Expected `=` after the type");
+                        ::std::process::exit(3);
+                    },
+                }
+            },
+            State::MERGE_STRATEGY { path, kind } => {
+                let strategy = match tok.value {
+                    TokValue::IDENTIFIER { ref value } => {
+                        match value.as_str() {
+                            "replace" => MergeStrategy::Replace,
+                            "append" => MergeStrategy::Append,
+                            "prepend" => MergeStrategy::Prepend,
+                            "delete" => {
+                                unset!(tok, path);
+                                continue;
+                            },
+                            _ => {
+                                generator_loc_error!(tok, "Unknown merge strategy: `{}`", value);
+                                ::std::process::exit(3);
+                            },
+                        }
+                    },
+                    _ => {
+                        generator_loc_error!(tok, "Expected merge strategy");
+                        ::std::process::exit(3);
+                    },
+                };
+                state = State::VALUE { path, strategy, kind };
+            },
+            State::VALUE { ref path, strategy, kind } => {
+                if matches!(strategy, MergeStrategy::Append | MergeStrategy::Prepend) && !matches!(tok.value, TokValue::SPECCHAR { ref value } if value == "[") {
+                    generator_loc_error!(tok, "Expected list after `!append` or `!prepend`");
+                    ::std::process::exit(3);
+                }
+                match tok.value {
+                    TokValue::STRING {
+                        value: text,
+                        quote_type: _,
+                    } => {
+                        store!(tok, Some(path.clone()), strategy, typed!(tok, path, kind, string_item!(tok, text)));
+                    },
+                    TokValue::NUMBER { value } => {
+                        store!(tok, Some(path.clone()), strategy, typed!(tok, path, kind, Item::Number(value)));
+                    },
+                    TokValue::BOOLEAN { value } => {
+                        store!(tok, Some(path.clone()), strategy, typed!(tok, path, kind, Item::Boolean(value)));
+                    },
+                    TokValue::REFERENCE { ref value } => {
+                        let full_path = full_path!(path);
+                        store!(tok, Some(path.clone()), strategy, reference_item!(tok, value));
+                        match (kind, full_path) {
+                            (Some(kind), Some(full_path)) => {
+                                provenance.types.insert(full_path, (kind, tok.loc.clone()));
+                            },
+                            (Some(_), None) => {
+                                generator_loc_warning!(tok, "The type of a reference is not checked inside a list");
+                            },
+                            (None, _) => {},
+                        }
+                    },
+                    TokValue::IDENTIFIER { ref value } if kind.is_none() && strategy == MergeStrategy::Merge => {
+                        match schema::Type::from_name(value) {
+                            Some(kind) => {
+                                state = State::TYPE_EQUALS { path: path.clone(), kind };
+                            },
+                            None => {
+                                generator_loc_error!(tok, "Unknown type: `{}`", value);
+                                ::std::process::exit(3);
+                            },
+                        }
+                    },
+                    TokValue::SPECCHAR { ref value } if value == "!" && strategy == MergeStrategy::Merge => {
+                        state = State::MERGE_STRATEGY { path: path.clone(), kind };
+                    },
+                    TokValue::SPECCHAR { ref value } if value == "{" => {
+                        check_type!(tok, path, kind, schema::Type::Table);
+                        open_table!(tok, Some(path.clone()), strategy);
+                    },
+                    TokValue::SPECCHAR { ref value } if value == "[" => {
+                        check_type!(tok, path, kind, schema::Type::List);
+                        open_list!(tok, Some(path.clone()), strategy);
+                    },
+                    _ => {
+                        generator_loc_error!(tok, "Expected string");
+                        ::std::process::exit(3);
+                    }
+                }
+            },
+            State::LIST_VALUE => {
+                match tok.value {
+                    TokValue::STRING {
+                        value: text,
+                        quote_type: _,
+                    } => {
+                        store!(tok, None, MergeStrategy::Merge, string_item!(tok, text));
+                    },
+                    TokValue::NUMBER { value } => {
+                        store!(tok, None, MergeStrategy::Merge, Item::Number(value));
+                    },
+                    TokValue::BOOLEAN { value } => {
+                        store!(tok, None, MergeStrategy::Merge, Item::Boolean(value));
+                    },
+                    TokValue::REFERENCE { ref value } => {
+                        store!(tok, None, MergeStrategy::Merge, reference_item!(tok, value));
+                    },
+                    TokValue::SPECCHAR { ref value } if value == "{" => {
+                        open_table!(tok, None, MergeStrategy::Merge);
+                    },
+                    TokValue::SPECCHAR { ref value } if value == "[" => {
+                        open_list!(tok, None, MergeStrategy::Merge);
+                    },
+                    TokValue::SPECCHAR { ref value } if value == "]" => {
+                        close_frame!(tok);
+                    },
+                    _ => {
+                        generator_loc_error!(tok, "Expected list element");
+                        ::std::process::exit(3);
+                    }
+                }
+            },
+            State::LIST_COMMA => {
+                match tok.value {
+                    TokValue::SPECCHAR { ref value } if value == "," => {
+                        state = State::LIST_VALUE;
+                    },
+                    TokValue::SPECCHAR { ref value } if value == "]" => {
+                        close_frame!(tok);
+                    },
+                    _ => {
+                        generator_loc_error!(tok, "Expected `,` or `]`");
+                        ::std::process::exit(3);
+                    }
+                }
+            },
+            State::UNSET { mut path } => {
+                match tok.value {
+                    TokValue::IDENTIFIER { value } | TokValue::STRING { value, .. } => {
+                        path.push(value);
+                    },
+                    _ => {
+                        generator_loc_error!(tok, "Expected identifier");
+                        ::std::process::exit(3);
+                    },
+                }
+                let dot = match lexed.peek() {
+                    Some(Tok { value: TokValue::SPECCHAR { value }, loc, .. }) => {
+                        value == "." && loc.line_number == tok.loc.line_number
+                    },
+                    _ => false,
+                };
+                if dot {
+                    lexed.next();
+                    state = State::UNSET { path };
+                } else {
+                    unset!(tok, path);
+                }
+            },
+            State::INCLUDE { format } => {
+                match tok.value {
+                    // `include json 'file'`
+                    TokValue::IDENTIFIER { ref value } if format.is_none() => {
+                        match InputFormat::from_name(value) {
+                            Some(format) => {
+                                state = State::INCLUDE { format: Some(format) };
+                            },
+                            None => {
+                                generator_loc_error!(tok, "Unknown input format: `{}`", value);
+                                ::std::process::exit(3);
+                            },
+                        }
+                    },
+                    TokValue::STRING {
+                        value,
+                        quote_type: _,
+                    } => {
+                        let config = Config {
+                            input_file_name: Some(value.clone()),
+                            starting_line_number: 1,
+                            starting_column_number: 1,
+                            display_file_name: None,
+                            input_format: format,
+                            ..config.clone()
+                        };
+                        let prefix = full_path!(Vec::<String>::new()).unwrap();
+                        match stack.last_mut().unwrap() {
+                            Frame::Table { table, .. } => {
+                                let base = ::std::mem::take(table);
+                                *table = generate_file_layer(program_name.clone(), config, base, prefix, provenance);
+                            },
+                            Frame::List { .. } => unreachable!(),
+                        }
+                        state = State::KEY;
+                    },
+                    _ => {
+                        generator_loc_error!(tok, "Expected string");
+                        ::std::process::exit(3);
+                    }
+                }
+            },
+            _ => unreachable!(),
         }
     }
-    return result;
+    if stack.len() != 1 {
+        generator_error!("{}: Unexpected end of file: unclosed `{{` or `[`", config.input_file_name.unwrap());
+        ::std::process::exit(3);
+    }
+    return match stack.pop().unwrap() {
+        Frame::Table { table, .. } => table,
+        Frame::List { .. } => unreachable!(),
+    };
 }
 
-fn generate_output(program_name: String, hashmap: ::std::collections::HashMap<String, Item>, config: Config) -> String {
-    eprintln!("hashmap: {:#?}", hashmap);
-
+// Replaces every `${...}` with the value it refers to
+fn resolve_references(hashmap: ::std::collections::HashMap<String, Item>, config: &Config) -> ::std::collections::HashMap<String, Item> {
     #[allow(unused_macros)]
-    macro_rules! generator_error {
+    macro_rules! resolver_error {
         ($($msg: expr),+) => {
-            error!("output generator: {}", format!($($msg,)+));
+            error!("resolver: {}", format!($($msg,)+));
         };
     }
 
     #[allow(unused_macros)]
-    macro_rules! generator_warning {
-        ($($msg: expr),+) => {
-            warning!("output generator: {}", format!($($msg,)+));
+    macro_rules! resolver_loc_error {
+        ($loc: expr, $($msg: expr),+) => {
+            resolver_error!("{}: {}: {}: {}", $loc.filename, $loc.line_number, $loc.column_number, format!($($msg,)+));
         };
     }
 
-    let mut result = String::new();
+    type Table = ::std::collections::HashMap<String, Item>;
 
-    match config.format {
-        Format::Vim => {
-            result += &format!("let g:{} = {{\n", config.variable_name);
-        },
-        Format::JSON => {
-            result += &format!("{{\n");
-        },
+    fn lookup(config: &Config, root: &Table, table: &Table, path: &[String], visiting: &mut Vec<Vec<String>>) -> Option<Item> {
+        let item = table.get(&path[0])?;
+        if path.len() == 1 {
+            return Some(resolve(config, root, item, visiting));
+        }
+        match item {
+            Item::Table(table) => lookup(config, root, table, &path[1..], visiting),
+            Item::Reference { .. } => {
+                match resolve(config, root, item, visiting) {
+                    Item::Table(table) => lookup(config, root, &table, &path[1..], visiting),
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
     }
 
-    let mut count = hashmap.keys().count();
-    for key in hashmap.keys() {
-        if count == 0 {
-            break;
+    fn resolve_reference(config: &Config, root: &Table, reference: &Reference, loc: &Loc, visiting: &mut Vec<Vec<String>>) -> Item {
+        match reference {
+            Reference::Key(path) => {
+                if let Some(start) = visiting.iter().position(|visited| visited == path) {
+                    let mut cycle: Vec<String> = visiting[start..].iter().map(|visited| visited.join(".")).collect();
+                    cycle.push(path.join("."));
+                    resolver_loc_error!(loc, "Reference cycle: {}", cycle.join(" -> "));
+                    ::std::process::exit(3);
+                }
+                visiting.push(path.clone());
+                let item = match lookup(config, root, root, path, visiting) {
+                    Some(item) => item,
+                    None => {
+                        resolver_loc_error!(loc, "Undefined reference: `{}`", path.join("."));
+                        ::std::process::exit(3);
+                    },
+                };
+                visiting.pop();
+                item
+            },
+            Reference::Env { name, default } => {
+                if config.no_env {
+                    resolver_loc_error!(loc, "Cannot read environment variable `{}`: environment access is disabled by --no-env", name);
+                    ::std::process::exit(3);
+                }
+                match (::std::env::var(name), default) {
+                    (Ok(value), Some(default)) if value.is_empty() => Item::Text(default.to_string()),
+                    (Ok(value), _) => Item::Text(value),
+                    (Err(_), Some(default)) => Item::Text(default.to_string()),
+                    (Err(e), None) => {
+                        resolver_loc_error!(loc, "Cannot read environment variable `{}`: {}", name, e);
+                        ::std::process::exit(3);
+                    },
+                }
+            },
+            Reference::Argument { index, default } => {
+                match (config.arguments.get(index - 1), default) {
+                    (Some(argument), _) => Item::Text(argument.to_string()),
+                    (None, Some(default)) => Item::Text(default.to_string()),
+                    (None, None) => {
+                        resolver_loc_error!(loc, "No argument {} (only {} given after `--`)", index, config.arguments.len());
+                        ::std::process::exit(3);
+                    },
+                }
+            },
+            Reference::Parameter { key, default } => {
+                match (config.parameters.iter().rev().find(|(name, _)| name == key), default) {
+                    (Some((_, value)), _) => parse_parameter(value),
+                    (None, Some(default)) => parse_parameter(default),
+                    (None, None) => {
+                        resolver_loc_error!(loc, "Parameter `{}` is not set (use -D {}=VALUE)", key, key);
+                        ::std::process::exit(3);
+                    },
+                }
+            },
         }
-        let value = match &hashmap[key] {
-            Item::Text(text) => {
-                let mut value = String::from("\"");
-                value += &text.to_string();
-                value += "\"";
-                value
+    }
+
+    fn resolve(config: &Config, root: &Table, item: &Item, visiting: &mut Vec<Vec<String>>) -> Item {
+        match item {
+            Item::Reference { reference, loc } => {
+                resolve_reference(config, root, reference, loc, visiting)
             },
-            Item::Number(number) => {
-                number.to_string()
+            Item::Template { parts, loc } => {
+                let mut text = String::new();
+                for part in parts {
+                    match part {
+                        TemplatePart::Text(part) => {
+                            text += part;
+                        },
+                        TemplatePart::Reference(reference) => {
+                            match resolve_reference(config, root, reference, loc, visiting) {
+                                Item::Table(_) | Item::List(_) => {
+                                    resolver_loc_error!(loc, "Cannot interpolate a table or a list: `${{{}}}`", reference.to_string());
+                                    ::std::process::exit(3);
+                                },
+                                item => {
+                                    text += &item.to_string();
+                                },
+                            }
+                        },
+                    }
+                }
+                Item::Text(text)
             },
-            Item::Boolean(value) => {
-                value.to_string()
+            Item::Table(table) => {
+                Item::Table(table.iter().map(|(key, item)| (key.clone(), resolve(config, root, item, visiting))).collect())
+            },
+            Item::List(list) => {
+                Item::List(list.iter().map(|item| resolve(config, root, item, visiting)).collect())
             },
             Item::Item(item) => {
-                item.to_string()
+                Item::Item(Box::new(resolve(config, root, item, visiting)))
             },
-        };
+            _ => item.clone(),
+        }
+    }
+
+    return hashmap.iter().map(|(key, item)| (key.clone(), resolve(config, &hashmap, item, &mut Vec::new()))).collect();
+}
+
+fn generate_value(config: &Config, item: &Item, depth: usize) -> String {
+    let indent = |depth: usize| -> String {
         match config.format {
-            Format::Vim => {
-                result += &format!("\\    '{}': {},\n", key, repr(&config, value));
-            },
-            Format::JSON => {
-                result += &format!("    \"{}\": {}", key, repr(&config, value));
-                if count != 1 {
+            Format::Vim => format!("\\{}", "    ".repeat(depth)),
+            Format::JSON => "    ".repeat(depth),
+            Format::Env | Format::Sh | Format::Smartconf => unreachable!(),
+        }
+    };
+    match item {
+        Item::Table(table) => {
+            let mut result = String::from("{\n");
+            let mut count = table.keys().count();
+            for (key, item) in table {
+                let value = generate_value(config, item, depth + 1);
+                match config.format {
+                    Format::Vim => {
+                        result += &format!("{}{}: {},\n", indent(depth + 1), key_repr(config, key.to_string()), value);
+                    },
+                    Format::JSON => {
+                        result += &format!("{}{}: {}", indent(depth + 1), key_repr(config, key.to_string()), value);
+                        if count != 1 {
+                            result += ",";
+                        }
+                        result += "\n";
+                    },
+                    Format::Env | Format::Sh | Format::Smartconf => unreachable!(),
+                }
+                count -= 1;
+            }
+            result += &indent(depth);
+            result += "}";
+            result
+        },
+        Item::List(list) => {
+            let mut result = String::from("[\n");
+            let mut count = list.len();
+            for item in list {
+                result += &format!("{}{}", indent(depth + 1), generate_value(config, item, depth + 1));
+                if config.format == Format::Vim || count != 1 {
                     result += ",";
                 }
                 result += "\n";
-            },
+                count -= 1;
+            }
+            result += &indent(depth);
+            result += "]";
+            result
+        },
+        Item::Text(text) => {
+            repr(config, text.to_string())
+        },
+        Item::Number(number) => {
+            number.to_string()
+        },
+        Item::Boolean(value) => {
+            value.to_string()
+        },
+        Item::Item(item) => {
+            generate_value(config, item, depth)
+        },
+        Item::Reference { .. } | Item::Template { .. } => {
+            unreachable!()
+        },
+    }
+}
+
+// `s` as a dotenv value, quoted only when needed
+fn env_repr(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "_./:@%+,=-".contains(c)) {
+        return s.to_string();
+    }
+    if !s.contains(['\'', '\n', '\r']) {
+        return format!("'{}'", s);
+    }
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => result += "\\\\",
+            '"' => result += "\\\"",
+            '$' => result += "\\$",
+            '\n' => result += "\\n",
+            '\t' => result += "\\t",
+            '\r' => result += "\\r",
+            _ => result.push(c),
         }
-        count -= 1;
     }
+    result.push('"');
+    return result;
+}
+
+// `s` in single quotes for a POSIX shell
+fn sh_repr(s: &str) -> String {
+    return format!("'{}'", s.replace('\'', "'\\''"));
+}
+
+// Variables of `item`, which is called `name`, for the env and sh
+// formats: the keys of nested tables are joined with the separator,
+// and the items of lists are numbered from 0
+fn generate_variables(config: &Config, name: &str, item: &Item, result: &mut String) {
+    let join = |key: &str| -> String {
+        let key = if config.uppercase { key.to_uppercase() } else { key.to_string() };
+        if name.is_empty() {
+            key
+        } else {
+            format!("{}{}{}", name, config.separator, key)
+        }
+    };
+    match item {
+        Item::Table(table) => {
+            let mut keys: Vec<&String> = table.keys().collect();
+            keys.sort();
+            for key in keys {
+                generate_variables(config, &join(key), &table[key], result);
+            }
+        },
+        Item::List(list) => {
+            for (index, item) in list.iter().enumerate() {
+                generate_variables(config, &join(&index.to_string()), item, result);
+            }
+        },
+        Item::Item(item) => {
+            generate_variables(config, name, item, result);
+        },
+        _ => {
+            if !dotenv::is_name(name) {
+                error!("output generator: `{}` is not a valid variable name", name);
+                ::std::process::exit(3);
+            }
+            match config.format {
+                Format::Env => {
+                    *result += &format!("{}={}\n", name, env_repr(&item.to_string()));
+                },
+                Format::Sh => {
+                    *result += &format!("export {}={}\n", name, sh_repr(&item.to_string()));
+                },
+                Format::Vim | Format::JSON | Format::Smartconf => unreachable!(),
+            }
+        },
+    }
+}
+
+fn generate_output(program_name: String, item: Item, config: Config) -> String {
+    eprintln!("item: {:#?}", item);
+
+    #[allow(unused_macros)]
+    macro_rules! generator_error {
+        ($($msg: expr),+) => {
+            error!("output generator: {}", format!($($msg,)+));
+        };
+    }
+
+    #[allow(unused_macros)]
+    macro_rules! generator_warning {
+        ($($msg: expr),+) => {
+            warning!("output generator: {}", format!($($msg,)+));
+        };
+    }
+
+    let mut result = String::new();
 
     match config.format {
         Format::Vim => {
-            result += &format!("\\}}\n");
+            result += &format!("let g:{} = ", config.variable_name);
         },
-        Format::JSON => {
-            result += &format!("}}\n");
+        Format::JSON => {},
+        Format::Env | Format::Sh => {
+            generate_variables(&config, "", &item, &mut result);
+            return result;
+        },
+        Format::Smartconf => {
+            return fmt::item(&item);
         },
     }
 
+    result += &generate_value(&config, &item, 0);
+    result += "\n";
+
     return result;
 }
 
@@ -1028,9 +2829,222 @@ fn main() {
     }
     let config = parse_cla(program_name.clone(), args);
     handle_cla(program_name.clone(), config.clone());
-    let lexed = lex(program_name.clone(), config.clone());
     eprintln!("{:?}", config);
-    let hashmap = generate_hashmap(program_name.clone(), lexed, config.clone());
-    let output = generate_output(program_name, hashmap, config);
-    println!("{}", output);
+    if let Command::Lsp = config.command {
+        ::std::process::exit(lsp::run(program_name, config));
+    }
+    if config.watch {
+        watch::run(config);
+    }
+    if let Command::Set { ref key, ref value } = config.command {
+        let input_file_name = config.input_file_names[0].clone();
+        let source = match read_input(&input_file_name) {
+            Ok(source) => source,
+            Err(e) => {
+                error!("Cannot read file: {}: {}", input_file_name, e);
+                ::std::process::exit(2);
+            },
+        };
+        let lexed = lex_source(program_name.clone(), Config {
+            input_file_name: Some(input_file_name.clone()),
+            ..config.clone()
+        }, source.clone());
+        let output = cst::Cst::new(source, lexed).set(key, value);
+        write_output(&config, Some(&input_file_name), &output);
+        return;
+    }
+    if let Command::Fmt = config.command {
+        let mut unformatted = false;
+        for input_file_name in &config.input_file_names {
+            let source = match read_input(input_file_name) {
+                Ok(source) => source,
+                Err(e) => {
+                    error!("Cannot read file: {}: {}", input_file_name, e);
+                    ::std::process::exit(2);
+                },
+            };
+            let lexed = lex_source(program_name.clone(), Config {
+                input_file_name: Some(input_file_name.clone()),
+                ..config.clone()
+            }, source.clone());
+            let cst = cst::Cst::new(source, lexed);
+            let output = match fmt::format(&cst) {
+                Ok(output) => output,
+                Err(index) => {
+                    match cst.toks.get(index) {
+                        Some(tok) => {
+                            error!("fmt: {}: {}: {}: Unexpected token: `{}`", tok.loc.filename, tok.loc.line_number, tok.loc.column_number, cst.text(index));
+                        },
+                        None => {
+                            error!("fmt: {}: Unexpected end of file", display_file_name(&config, input_file_name));
+                        },
+                    }
+                    ::std::process::exit(3);
+                },
+            };
+            if config.check {
+                if output != cst.source {
+                    eprintln!("{}: not formatted", input_file_name);
+                    unformatted = true;
+                }
+                continue;
+            }
+            if output != cst.source || config.output_file_name.is_some() || input_file_name == "-" {
+                write_output(&config, Some(input_file_name), &output);
+            }
+        }
+        if unformatted {
+            ::std::process::exit(6);
+        }
+        return;
+    }
+    if let Command::Lint = config.command {
+        let mut output = String::new();
+        for input_file_name in &config.input_file_names {
+            let source = match read_input(input_file_name) {
+                Ok(source) => source,
+                Err(e) => {
+                    error!("Cannot read file: {}: {}", input_file_name, e);
+                    ::std::process::exit(2);
+                },
+            };
+            let config = Config {
+                input_file_name: Some(input_file_name.clone()),
+                ..config.clone()
+            };
+            let cst = cst::Cst::new(source.clone(), lex_source(program_name.clone(), config.clone(), source.clone()));
+            let mut provenance = Provenance::default();
+            let lexed = lex_source(program_name.clone(), config.clone(), source);
+            generate_hashmap_layer(program_name.clone(), lexed, config.clone(), ::std::collections::HashMap::new(), Vec::new(), &mut provenance);
+            let file_name = display_file_name(&config, input_file_name);
+            for finding in lint::lint(&program_name, &config, &cst, &provenance, &file_name) {
+                output += &format!("{}: {}: {}: {} [{}]\n", finding.loc.filename, finding.loc.line_number, finding.loc.column_number, finding.message, finding.rule);
+            }
+        }
+        write_output(&config, None, &output);
+        if !output.is_empty() {
+            ::std::process::exit(6);
+        }
+        return;
+    }
+    if let (Command::JsonSchema, Some(ref schema_file_name)) = (&config.command, &config.schema_file_name) {
+        let schema = schema::load_file(&program_name, &config, schema_file_name, &mut Provenance::default());
+        write_output(&config, None, &format!("{}\n", json_schema::from_schema(&schema).pretty(0)));
+        return;
+    }
+    let mut provenance = Provenance::default();
+    let mut hashmap = generate_hashmap(program_name.clone(), config.clone(), &mut provenance);
+    let schema = schema::take(&program_name, &config, &mut hashmap, &mut provenance);
+    if let (Some(ref schema), false) = (&schema, config.no_defaults) {
+        schema.apply_defaults(&mut hashmap, &[], &mut provenance);
+    }
+    let output = match config.command {
+        Command::Get { ref key } => {
+            let item = match get_path(&hashmap, key) {
+                Some(item) => item.clone(),
+                None => {
+                    error!("Key not found: `{}`", key_path_to_string(key));
+                    ::std::process::exit(5);
+                },
+            };
+            match item {
+                // Variable names start with the key
+                Item::Table(_) | Item::List(_) if matches!(config.format, Format::Env | Format::Sh) => {
+                    let item = key.iter().rev().fold(item, |item, key| {
+                        Item::Table(::std::collections::HashMap::from([(key.clone(), item)]))
+                    });
+                    generate_output(program_name, item, config.clone())
+                },
+                Item::Table(_) | Item::List(_) => {
+                    generate_output(program_name, item, config.clone())
+                },
+                _ => {
+                    format!("{}\n", item.to_string())
+                },
+            }
+        },
+        Command::Set { .. } | Command::Fmt | Command::Lint | Command::Lsp => unreachable!(),
+        Command::JsonSchema => {
+            let json = match schema {
+                Some(ref schema) => json_schema::from_schema(schema),
+                None => json_schema::infer(&hashmap),
+            };
+            format!("{}\n", json.pretty(0))
+        },
+        Command::Validate => {
+            let schema = match schema {
+                Some(schema) => schema,
+                None => {
+                    error!("validate: No schema: use --schema or a `{}` key", schema::SCHEMA_KEY);
+                    ::std::process::exit(1);
+                },
+            };
+            let mut errors = Vec::new();
+            schema.check(&Item::Table(hashmap), &[], "", &provenance, &mut errors);
+            errors.sort_by_key(|(loc, _)| loc.as_ref().map(|loc| (loc.filename.clone(), loc.line_number, loc.column_number)));
+            for (loc, message) in &errors {
+                match loc {
+                    Some(loc) => {
+                        error!("validator: {}: {}: {}: {}", loc.filename, loc.line_number, loc.column_number, message);
+                    },
+                    None => {
+                        error!("validator: {}", message);
+                    },
+                }
+            }
+            if !errors.is_empty() {
+                ::std::process::exit(6);
+            }
+            String::new()
+        },
+        Command::Generate if config.provenance => {
+            let mut keys: Vec<(&Vec<String>, &Loc)> = provenance.keys.iter().collect();
+            keys.sort_by(|a, b| a.0.cmp(b.0));
+            let mut output = String::new();
+            for (path, loc) in keys {
+                output += &format!("{}: {}: {}: {}\n", key_path_to_string(path), loc.filename, loc.line_number, loc.column_number);
+            }
+            output
+        },
+        Command::Generate => {
+            generate_output(program_name, Item::Table(hashmap), config.clone())
+        },
+    };
+    write_output(&config, None, &output);
+    if let Some(ref dependency_file_name) = config.dependency_file_name {
+        let target = config.output_file_name.clone().unwrap();
+        let rule = make_rule(&target, &provenance.files);
+        if let Err(e) = write_file_atomically(dependency_file_name, &rule) {
+            error!("Cannot write file: {}: {}", dependency_file_name, e);
+            ::std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRINGS: &[&str] = &["", "plain", "a b", "it's", "''", "a'b'c", "$HOME `id` \\ \"q\"", "line\nbreak\ttab", "${x}", "-n", "*"];
+
+    #[test]
+    fn sh_repr_survives_the_shell() {
+        for s in STRINGS {
+            let output = ::std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf '%s' {}", sh_repr(s)))
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), *s);
+        }
+    }
+
+    #[test]
+    fn env_repr_reads_back() {
+        for s in STRINGS {
+            let (table, _) = dotenv::parse(&format!("X={}\n", env_repr(s))).unwrap();
+            assert_eq!(table["X"].to_string(), *s, "{}", env_repr(s));
+        }
+    }
 }
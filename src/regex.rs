@@ -0,0 +1,481 @@
+// Small regular expressions for schema `pattern`s.
+//
+// Supported: literals, `.`, `[...]` and `[^...]` classes with ranges,
+// `\d \w \s \D \W \S` and escaped punctuation, `^`, `$`, groups,
+// `|`, and the quantifiers `* + ? {n} {n,} {n,m}`. Like in JSON
+// Schema, a pattern matches anywhere in the string unless it is
+// anchored.
+// Patterns are compiled to an NFA that is run on all its states at
+// once, so matching takes time linear in the text whatever the
+// pattern, and `(a+)+$` cannot backtrack forever.
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    Alternation(Vec<Vec<Node>>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize> },
+}
+
+// NFA instructions; `Split` and `Jump` move to other instructions
+// without reading a character
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+}
+
+// Repetition counts and compiled patterns are limited, as every
+// repetition is a copy of the repeated instructions
+const MAX_COUNT: usize = 1000;
+const MAX_PROGRAM: usize = 100_000;
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn alternation(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.index += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantifier(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    // `\x` outside or inside a class
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(String::from("Trailing `\\`")),
+        };
+        self.index += 1;
+        let (ranges, negated) = match c {
+            'd' => (DIGIT, false),
+            'w' => (WORD, false),
+            's' => (SPACE, false),
+            'D' => (DIGIT, true),
+            'W' => (WORD, true),
+            'S' => (SPACE, true),
+            'n' => return Ok(Node::Char('\n')),
+            't' => return Ok(Node::Char('\t')),
+            'r' => return Ok(Node::Char('\r')),
+            c if c.is_alphanumeric() => return Err(format!("Unknown escape: `\\{}`", c)),
+            c => return Ok(Node::Char(c)),
+        };
+        Ok(Node::Class { ranges: ranges.to_vec(), negated })
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().unwrap();
+        self.index += 1;
+        match c {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '\\' => self.escape(),
+            '(' => {
+                // Groups only group, `(?:` is the same
+                if self.chars[self.index..].starts_with(&['?', ':']) {
+                    self.index += 2;
+                }
+                let alternatives = self.alternation()?;
+                if self.peek() != Some(')') {
+                    return Err(String::from("Missing `)`"));
+                }
+                self.index += 1;
+                Ok(Node::Alternation(alternatives))
+            },
+            '[' => self.class(),
+            '*' | '+' | '?' | '{' => Err(format!("Nothing to repeat before `{}`", c)),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.index += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(String::from("Missing `]`")),
+            };
+            self.index += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let start = match c {
+                '\\' => match self.escape()? {
+                    Node::Char(c) => c,
+                    Node::Class { ranges: escaped, negated: false } => {
+                        ranges.extend(escaped);
+                        continue;
+                    },
+                    _ => return Err(String::from("Negated escape in a class")),
+                },
+                c => c,
+            };
+            if self.peek() == Some('-') && self.chars.get(self.index + 1).is_some_and(|c| *c != ']') {
+                self.index += 1;
+                let end = match self.peek() {
+                    Some('\\') => {
+                        self.index += 1;
+                        match self.escape()? {
+                            Node::Char(c) => c,
+                            _ => return Err(String::from("Class in a range")),
+                        }
+                    },
+                    Some(c) => {
+                        self.index += 1;
+                        c
+                    },
+                    None => return Err(String::from("Missing `]`")),
+                };
+                if end < start {
+                    return Err(format!("Invalid range: `{}-{}`", start, end));
+                }
+                ranges.push((start, end));
+            } else {
+                ranges.push((start, start));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.index;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.index += 1;
+        }
+        self.chars[start..self.index].iter().collect::<String>().parse().ok()
+    }
+
+    fn quantifier(&mut self, node: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.index += 1;
+                let min = match self.number() {
+                    Some(min) => min,
+                    None => return Err(String::from("Expected a number after `{`")),
+                };
+                let max = match self.peek() {
+                    Some(',') => {
+                        self.index += 1;
+                        self.number()
+                    },
+                    _ => Some(min),
+                };
+                if self.peek() != Some('}') {
+                    return Err(String::from("Missing `}`"));
+                }
+                if max.is_some_and(|max| max < min) {
+                    return Err(format!("Invalid repetition: {{{},{}}}", min, max.unwrap()));
+                }
+                if max.unwrap_or(min) > MAX_COUNT {
+                    return Err(String::from("Repetition too large"));
+                }
+                (min, max)
+            },
+            _ => return Ok(node),
+        };
+        self.index += 1;
+        if matches!(node, Node::Start | Node::End) {
+            return Err(String::from("Cannot repeat an anchor"));
+        }
+        Ok(Node::Repeat { node: Box::new(node), min, max })
+    }
+}
+
+fn in_class(c: char, ranges: &[(char, char)], negated: bool) -> bool {
+    ranges.iter().any(|(start, end)| *start <= c && c <= *end) != negated
+}
+
+// Number of instructions `compile_node` makes of `node`
+fn program_size(node: &Node) -> usize {
+    match node {
+        Node::Char(_) | Node::Any | Node::Class { .. } | Node::Start | Node::End => 1,
+        Node::Alternation(alternatives) => alternatives.iter().fold(0, |size: usize, sequence| {
+            let sequence = sequence.iter().fold(0, |size: usize, node| size.saturating_add(program_size(node)));
+            size.saturating_add(sequence).saturating_add(2)
+        }),
+        Node::Repeat { node, min, max } => {
+            let size = program_size(node);
+            let optional = match max {
+                Some(max) => (max - min).saturating_mul(size.saturating_add(1)),
+                None => size.saturating_add(2),
+            };
+            min.saturating_mul(size).saturating_add(optional)
+        },
+    }
+}
+
+fn compile_sequence(nodes: &[Node], program: &mut Vec<Inst>) {
+    for node in nodes {
+        compile_node(node, program);
+    }
+}
+
+fn compile_node(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class { ranges, negated } => program.push(Inst::Class { ranges: ranges.clone(), negated: *negated }),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Alternation(alternatives) => {
+            let mut jumps = Vec::new();
+            for (index, sequence) in alternatives.iter().enumerate() {
+                if index + 1 == alternatives.len() {
+                    compile_sequence(sequence, program);
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile_sequence(sequence, program);
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            for jump in jumps {
+                program[jump] = Inst::Jump(program.len());
+            }
+        },
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile_node(node, program);
+            }
+            match max {
+                Some(max) => {
+                    for _ in *min..*max {
+                        let split = program.len();
+                        program.push(Inst::Split(split + 1, 0));
+                        compile_node(node, program);
+                        program[split] = Inst::Split(split + 1, program.len());
+                    }
+                },
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile_node(node, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                },
+            }
+        },
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            index: 0,
+        };
+        let root = Node::Alternation(parser.alternation()?);
+        if parser.index < parser.chars.len() {
+            return Err(String::from("Unmatched `)`"));
+        }
+        if program_size(&root) > MAX_PROGRAM {
+            return Err(String::from("Repetition too large"));
+        }
+        let mut program = Vec::new();
+        compile_node(&root, &mut program);
+        program.push(Inst::Match);
+        Ok(Regex { program })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let input: Vec<char> = text.chars().collect();
+        let mut threads = Vec::new();
+        // A new thread starts at every position, as the pattern is
+        // not anchored
+        if self.add_thread(input.len(), 0, 0, &mut threads, &mut vec![false; self.program.len()]) {
+            return true;
+        }
+        for (position, c) in input.iter().enumerate() {
+            let mut next = Vec::new();
+            let mut seen = vec![false; self.program.len()];
+            for pc in threads {
+                let accepted = match &self.program[pc] {
+                    Inst::Char(expected) => c == expected,
+                    Inst::Any => *c != '\n',
+                    Inst::Class { ranges, negated } => in_class(*c, ranges, *negated),
+                    _ => unreachable!(),
+                };
+                if accepted && self.add_thread(input.len(), position + 1, pc + 1, &mut next, &mut seen) {
+                    return true;
+                }
+            }
+            if self.add_thread(input.len(), position + 1, 0, &mut next, &mut seen) {
+                return true;
+            }
+            threads = next;
+        }
+        false
+    }
+
+    // Adds the instructions reading a character that `pc` leads to at
+    // `position` to `threads`; true when it leads to a match
+    fn add_thread(&self, length: usize, position: usize, pc: usize, threads: &mut Vec<usize>, seen: &mut [bool]) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if seen[pc] {
+                continue;
+            }
+            seen[pc] = true;
+            match self.program[pc] {
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                },
+                Inst::Jump(to) => stack.push(to),
+                Inst::Start if position == 0 => stack.push(pc + 1),
+                Inst::End if position == length => stack.push(pc + 1),
+                Inst::Start | Inst::End => {},
+                Inst::Match => return true,
+                Inst::Char(_) | Inst::Any | Inst::Class { .. } => threads.push(pc),
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(matches("b", "abc"));
+        assert!(matches("^ab", "abc"));
+        assert!(!matches("^b", "abc"));
+        assert!(matches("bc$", "abc"));
+        assert!(!matches("b$", "abc"));
+        assert!(matches("^$", ""));
+        assert!(!matches("^$", "a"));
+        assert!(matches("^a|c$", "xxc"));
+        assert!(!matches("a^", "a"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("^[a-c]+$", "abcab"));
+        assert!(!matches("^[a-c]+$", "abd"));
+        assert!(matches("^[^0-9]$", "x"));
+        assert!(!matches("^[^0-9]$", "5"));
+        assert!(matches("^[-a]+$", "-a-"));
+        assert!(matches("^[a-]+$", "a-"));
+        assert!(matches("^[]a]+$", "]a"));
+        assert!(matches("^\\d\\w\\s$", "1_ "));
+        assert!(!matches("^\\D$", "1"));
+        assert!(matches("^[\\d.]+$", "1.5"));
+        assert!(matches("^\\.$", "."));
+        assert!(!matches("^\\.$", "x"));
+        assert!(!matches("^.$", "\n"));
+    }
+
+    #[test]
+    fn alternation() {
+        assert!(matches("^(cat|dog)s?$", "dogs"));
+        assert!(matches("^(cat|dog)s?$", "cat"));
+        assert!(!matches("^(cat|dog)s?$", "cow"));
+        assert!(matches("^(?:a|b|)c$", "c"));
+        assert!(matches("^(a|ab)(c|bcd)$", "abcd"));
+    }
+
+    #[test]
+    fn repetition() {
+        assert!(matches("^a*$", ""));
+        assert!(matches("^a+b$", "aaab"));
+        assert!(!matches("^a+b$", "b"));
+        assert!(matches("^ab?c$", "ac"));
+        assert!(matches("^a{3}$", "aaa"));
+        assert!(!matches("^a{3}$", "aa"));
+        assert!(!matches("^a{3}$", "aaaa"));
+        assert!(matches("^a{2,}$", "aaaaa"));
+        assert!(!matches("^a{2,}$", "a"));
+        assert!(matches("^a{1,2}b$", "aab"));
+        assert!(!matches("^a{1,2}b$", "aaab"));
+        assert!(matches("^(ab){2}$", "abab"));
+        assert!(matches("^(a*)*b$", "aab"));
+        assert!(matches("^(a?){3}$", "a"));
+    }
+
+    #[test]
+    fn pathological_backtracking() {
+        let text = format!("{}b", "a".repeat(5000));
+        for pattern in ["^(a+)+$", "^(a|a)*$", "^(a|aa)*c", "(a*)*$x", "^(a?){50}a{50}$"] {
+            assert!(!matches(pattern, &text), "{}", pattern);
+        }
+        assert!(matches("^(a?){50}a{50}$", &"a".repeat(50)));
+        assert!(matches("(a+)+b$", &text));
+        // A program close to the limit, run without recursion
+        let long = format!("^(a?){{1000}}(b?){{1000}}(c?){{1000}}{}$", "x?".repeat(40000));
+        assert!(matches(&long, "abx"));
+        assert!(!matches(&long, "ba"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Regex::new("(a").unwrap_err(), "Missing `)`");
+        assert_eq!(Regex::new("a)").unwrap_err(), "Unmatched `)`");
+        assert_eq!(Regex::new("[a").unwrap_err(), "Missing `]`");
+        assert_eq!(Regex::new("[b-a]").unwrap_err(), "Invalid range: `b-a`");
+        assert_eq!(Regex::new("*a").unwrap_err(), "Nothing to repeat before `*`");
+        assert_eq!(Regex::new("a{3,2}").unwrap_err(), "Invalid repetition: {3,2}");
+        assert_eq!(Regex::new("^*").unwrap_err(), "Cannot repeat an anchor");
+        assert_eq!(Regex::new("\\q").unwrap_err(), "Unknown escape: `\\q`");
+        assert_eq!(Regex::new("a{1001}").unwrap_err(), "Repetition too large");
+        assert_eq!(Regex::new("a{2,1001}").unwrap_err(), "Repetition too large");
+        assert_eq!(Regex::new("^(a?){200000}$").unwrap_err(), "Repetition too large");
+        assert_eq!(Regex::new("((a{1000}){1000}){1000}").unwrap_err(), "Repetition too large");
+    }
+}
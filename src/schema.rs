@@ -0,0 +1,458 @@
+// Schemas: which keys a config has and what their values look like.
+//
+// A schema is itself smartconf, a table from key to spec:
+//
+//     server: {
+//         required: true
+//         keys: {
+//             port: { type: 'int' min: 1 max: 65535 }
+//             mode: { enum: ['dev', 'prod'] }
+//         }
+//     }
+//
+// It is read from `--schema FILE` or from the `$schema` key of the
+// config, which is either a file name or the schema table itself.
+
+use ::std::collections::HashMap;
+
+use crate::regex::Regex;
+use crate::{Config, Item, Loc, Number, Provenance};
+
+pub const SCHEMA_KEY: &str = "$schema";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    String,
+    Int,
+    Float,
+    Number,
+    Bool,
+    Table,
+    List,
+    Any,
+}
+
+impl Type {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::String => "string",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Number => "number",
+            Type::Bool => "bool",
+            Type::Table => "table",
+            Type::List => "list",
+            Type::Any => "any",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Type> {
+        [Type::String, Type::Int, Type::Float, Type::Number, Type::Bool, Type::Table, Type::List, Type::Any]
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+
+    // Type of `item`, which has its references resolved
+    pub fn of(item: &Item) -> Type {
+        match item {
+            Item::Text(_) => Type::String,
+            Item::Number(Number::USIZE { .. }) => Type::Int,
+            Item::Number(Number::FLOAT { .. }) => Type::Float,
+            Item::Boolean(_) => Type::Bool,
+            Item::Item(item) => Type::of(item),
+            Item::Table(_) => Type::Table,
+            Item::List(_) => Type::List,
+            Item::Reference { .. } | Item::Template { .. } => Type::String,
+        }
+    }
+
+    pub fn accepts(&self, kind: Type) -> bool {
+        *self == Type::Any || *self == kind || (*self == Type::Number && matches!(kind, Type::Int | Type::Float))
+    }
+}
+
+#[derive(Debug)]
+pub struct Schema {
+    pub kind: Type,
+    pub required: bool,
+    pub enumeration: Option<Vec<Item>>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub pattern: Option<(String, Regex)>,
+    // Specs of the keys of a table, sorted by key
+    pub keys: Vec<(String, Schema)>,
+    // Whether a table may have keys that are not in `keys`
+    pub additional: bool,
+    pub items: Option<Box<Schema>>,
+    pub default: Option<Item>,
+    // Where `default` is written in the schema
+    pub default_loc: Option<Loc>,
+    pub description: Option<String>,
+}
+
+// Where an error in a schema or a config is, if anywhere
+pub type Error = (Option<Loc>, String);
+
+fn unwrap(item: &Item) -> &Item {
+    match item {
+        Item::Item(item) => unwrap(item),
+        _ => item,
+    }
+}
+
+fn number_value(item: &Item) -> Option<f64> {
+    match unwrap(item) {
+        Item::Number(Number::USIZE { value }) => Some(*value as f64),
+        Item::Number(Number::FLOAT { value }) => Some(*value),
+        _ => None,
+    }
+}
+
+fn same_value(a: &Item, b: &Item) -> bool {
+    match (unwrap(a), unwrap(b)) {
+        (Item::Text(a), Item::Text(b)) => a == b,
+        (Item::Boolean(a), Item::Boolean(b)) => a == b,
+        (a @ Item::Number(_), b @ Item::Number(_)) => a.to_string() == b.to_string(),
+        _ => false,
+    }
+}
+
+fn value_repr(item: &Item) -> String {
+    match unwrap(item) {
+        Item::Text(text) => crate::smartconf_repr(text, '\''),
+        item => item.to_string(),
+    }
+}
+
+// Location of `path`, or of the key below it that comes first in the
+// first file that sets one; the root has no location of its own
+pub fn locate(provenance: &Provenance, path: &[String]) -> Option<Loc> {
+    if path.is_empty() {
+        return None;
+    }
+    if let Some(loc) = provenance.keys.get(path) {
+        return Some(loc.clone());
+    }
+    let file_index = |loc: &Loc| provenance.files.iter().position(|file| *file == loc.filename).unwrap_or(usize::MAX);
+    provenance.keys.iter()
+        .filter(|(key, _)| key.starts_with(path))
+        .map(|(_, loc)| loc)
+        .min_by_key(|loc| (file_index(loc), loc.line_number, loc.column_number))
+        .cloned()
+}
+
+// Number of single-character edits from `a` to `b`
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + if ca == *cb { 0 } else { 1 };
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+impl Schema {
+    // Schema of a whole config from the table of its key specs
+    fn root(keys: Vec<(String, Schema)>) -> Schema {
+        Schema {
+            kind: Type::Table,
+            required: true,
+            enumeration: None,
+            min: None,
+            max: None,
+            pattern: None,
+            keys,
+            additional: false,
+            items: None,
+            default: None,
+            default_loc: None,
+            description: None,
+        }
+    }
+
+    // Specs of the keys of `table`, which is at `path` in the file
+    // `provenance` belongs to
+    fn keys(table: &HashMap<String, Item>, path: &[String], provenance: &Provenance) -> Result<Vec<(String, Schema)>, Error> {
+        let mut keys = Vec::new();
+        for (key, spec) in table {
+            let mut spec_path = path.to_vec();
+            spec_path.push(key.clone());
+            keys.push((key.clone(), Schema::from_spec(spec, &spec_path, provenance)?));
+        }
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(keys)
+    }
+
+    fn from_spec(spec: &Item, path: &[String], provenance: &Provenance) -> Result<Schema, Error> {
+        let field_path = |field: &str| {
+            let mut field_path = path.to_vec();
+            field_path.push(field.to_string());
+            field_path
+        };
+        macro_rules! spec_error {
+            ($path: expr, $($msg: expr),+) => {
+                return Err((locate(provenance, &$path), format!($($msg,)+)))
+            };
+        }
+        let table = match unwrap(spec) {
+            Item::Table(table) => table,
+            _ => spec_error!(path, "The spec of `{}` must be a table", crate::key_path_to_string(path)),
+        };
+        let mut schema = Schema::root(Vec::new());
+        schema.required = false;
+        schema.additional = true;
+        schema.kind = Type::Any;
+        let mut kind: Option<Type> = None;
+        let mut fields: Vec<(&String, &Item)> = table.iter().collect();
+        fields.sort_by(|a, b| a.0.cmp(b.0));
+        for (field, value) in fields {
+            let value = unwrap(value);
+            match (field.as_str(), value) {
+                ("type", Item::Text(name)) => match Type::from_name(name) {
+                    Some(found) => kind = Some(found),
+                    None => spec_error!(field_path(field), "Unknown type: `{}`", name),
+                },
+                ("required", Item::Boolean(required)) => schema.required = *required,
+                ("additional", Item::Boolean(additional)) => schema.additional = *additional,
+                ("enum", Item::List(values)) => schema.enumeration = Some(values.clone()),
+                ("min", _) | ("max", _) if number_value(value).is_some() => {
+                    let number = number_value(value);
+                    match field.as_str() {
+                        "min" => schema.min = number,
+                        _ => schema.max = number,
+                    }
+                },
+                ("pattern", Item::Text(pattern)) => match Regex::new(pattern) {
+                    Ok(regex) => schema.pattern = Some((pattern.clone(), regex)),
+                    Err(e) => spec_error!(field_path(field), "Invalid pattern: {}", e),
+                },
+                ("keys", Item::Table(keys)) => {
+                    schema.keys = Schema::keys(keys, &field_path(field), provenance)?;
+                    // A table with `keys` is closed unless `additional: true`
+                    schema.additional = table.get("additional").is_some_and(|additional| matches!(unwrap(additional), Item::Boolean(true)));
+                },
+                ("items", items) => {
+                    schema.items = Some(Box::new(Schema::from_spec(items, &field_path(field), provenance)?));
+                },
+                ("default", default) => {
+                    schema.default = Some(default.clone());
+                    schema.default_loc = locate(provenance, &field_path(field));
+                },
+                ("description", Item::Text(description)) => schema.description = Some(description.clone()),
+                ("type" | "required" | "additional" | "enum" | "min" | "max" | "pattern" | "keys" | "description", _) => {
+                    spec_error!(field_path(field), "Wrong type for `{}`", field);
+                },
+                _ => spec_error!(field_path(field), "Unknown field in a spec: `{}`", field),
+            }
+        }
+        schema.kind = match kind {
+            Some(kind) => kind,
+            None if !schema.keys.is_empty() => Type::Table,
+            None if schema.items.is_some() => Type::List,
+            None => match (&schema.default, &schema.enumeration) {
+                (Some(default), _) => Type::of(default),
+                (None, Some(values)) if !values.is_empty() => Type::of(&values[0]),
+                _ => Type::Any,
+            },
+        };
+        if let Some(ref default) = schema.default {
+            let mut errors = Vec::new();
+            schema.check(default, &field_path("default"), "default", provenance, &mut errors);
+            if let Some((_, message)) = errors.into_iter().next() {
+                spec_error!(field_path("default"), "{}", message);
+            }
+        }
+        Ok(schema)
+    }
+
+    // Checks `item`, which is `name` in messages and at `path` in
+    // `provenance`; adds what is wrong to `errors`
+    pub fn check(&self, item: &Item, path: &[String], name: &str, provenance: &Provenance, errors: &mut Vec<Error>) {
+        let item = unwrap(item);
+        let kind = Type::of(item);
+        macro_rules! check_error {
+            ($($msg: expr),+) => {
+                errors.push((locate(provenance, path), format!($($msg,)+)))
+            };
+        }
+        if !self.kind.accepts(kind) {
+            check_error!("`{}` should be {} {}, not {} {}", name, article(self.kind), self.kind.name(), article(kind), kind.name());
+            return;
+        }
+        if let Some(ref values) = self.enumeration {
+            if !values.iter().any(|value| same_value(value, item)) {
+                let values: Vec<String> = values.iter().map(value_repr).collect();
+                check_error!("`{}` should be one of {}, not {}", name, values.join(", "), value_repr(item));
+            }
+        }
+        if let Some(number) = number_value(item) {
+            if let Some(min) = self.min.filter(|min| number < *min) {
+                check_error!("`{}` should be at least {}, not {}", name, min, number);
+            }
+            if let Some(max) = self.max.filter(|max| number > *max) {
+                check_error!("`{}` should be at most {}, not {}", name, max, number);
+            }
+        }
+        if let (Some((pattern, regex)), Item::Text(text)) = (&self.pattern, item) {
+            if !regex.is_match(text) {
+                check_error!("`{}` does not match `{}`: {}", name, pattern, value_repr(item));
+            }
+        }
+        match item {
+            Item::Table(table) => {
+                for (key, schema) in &self.keys {
+                    let mut key_path = path.to_vec();
+                    key_path.push(key.clone());
+                    let key_name = crate::key_path_to_string(::std::slice::from_ref(key));
+                    let key_name = match name {
+                        "" => key_name,
+                        _ => format!("{}.{}", name, key_name),
+                    };
+                    match table.get(key) {
+                        Some(value) => schema.check(value, &key_path, &key_name, provenance, errors),
+                        None if schema.required && schema.default.is_none() => {
+                            errors.push((locate(provenance, path), format!("Missing required key `{}`", key_name)));
+                        },
+                        None => {},
+                    }
+                }
+                if !self.additional {
+                    let mut unknown: Vec<&String> = table.keys().filter(|key| !self.keys.iter().any(|(known, _)| known == *key)).collect();
+                    unknown.sort();
+                    for key in unknown {
+                        let mut key_path = path.to_vec();
+                        key_path.push(key.clone());
+                        let mut message = format!("Unknown key `{}`", crate::key_path_to_string(&key_path));
+                        let closest = self.keys.iter().map(|(known, _)| (distance(key, known), known)).min();
+                        if let Some((_, known)) = closest.filter(|(distance, _)| *distance <= 2) {
+                            message += &format!(", did you mean `{}`?", known);
+                        }
+                        errors.push((locate(provenance, &key_path), message));
+                    }
+                }
+            },
+            Item::List(list) => {
+                if let Some(ref items) = self.items {
+                    for (index, value) in list.iter().enumerate() {
+                        items.check(value, path, &format!("{}[{}]", name, index), provenance, errors);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Schema {
+    // Adds the defaults of the keys missing from `table`, which is at
+    // `path`, and of tables below it. A missing table is added if some
+    // key in it has a default.
+    pub fn apply_defaults(&self, table: &mut HashMap<String, Item>, path: &[String], provenance: &mut Provenance) {
+        for (key, schema) in &self.keys {
+            let mut key_path = path.to_vec();
+            key_path.push(key.clone());
+            if let Some(item) = table.get_mut(key) {
+                schema.apply_defaults_below(item, &key_path, provenance);
+                continue;
+            }
+            if let Some(ref default) = schema.default {
+                table.insert(key.clone(), default.clone());
+                if let Some(ref loc) = schema.default_loc {
+                    provenance.keys.insert(key_path, loc.clone());
+                }
+                continue;
+            }
+            let mut defaults = HashMap::new();
+            schema.apply_defaults(&mut defaults, &key_path, provenance);
+            if !defaults.is_empty() {
+                table.insert(key.clone(), Item::Table(defaults));
+            }
+        }
+    }
+
+    fn apply_defaults_below(&self, item: &mut Item, path: &[String], provenance: &mut Provenance) {
+        match item {
+            Item::Item(item) => self.apply_defaults_below(item, path, provenance),
+            Item::Table(table) => self.apply_defaults(table, path, provenance),
+            Item::List(list) => {
+                if let Some(ref items) = self.items {
+                    // Keys in lists have no provenance
+                    let mut provenance = Provenance::default();
+                    for item in list {
+                        items.apply_defaults_below(item, path, &mut provenance);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+pub fn article(kind: Type) -> &'static str {
+    match kind {
+        Type::Int | Type::Any => "an",
+        _ => "a",
+    }
+}
+
+// Reads the schema file `file_name`; it and the files it includes are
+// recorded in `provenance` as files the output depends on
+pub fn load_file(program_name: &str, config: &Config, file_name: &str, provenance: &mut Provenance) -> Schema {
+    let mut schema_provenance = Provenance::default();
+    let hashmap = crate::generate_hashmap(program_name.to_string(), Config {
+        input_file_names: vec![file_name.to_string()],
+        parameters: Vec::new(),
+        display_file_name: None,
+        starting_line_number: 1,
+        starting_column_number: 1,
+        list_files: None,
+        input_format: None,
+        ..config.clone()
+    }, &mut schema_provenance);
+    for file_name in &schema_provenance.files {
+        provenance.read_file(config, file_name);
+    }
+    match Schema::keys(&hashmap, &[], &schema_provenance) {
+        Ok(keys) => Schema::root(keys),
+        Err(e) => exit_with_schema_error(e),
+    }
+}
+
+fn exit_with_schema_error((loc, message): Error) -> ! {
+    match loc {
+        Some(loc) => {
+            error!("schema: {}: {}: {}: {}", loc.filename, loc.line_number, loc.column_number, message);
+        },
+        None => {
+            error!("schema: {}", message);
+        },
+    }
+    ::std::process::exit(3);
+}
+
+// Removes the `$schema` key from `hashmap` and returns the schema
+// from it or from --schema, which wins
+pub fn take(program_name: &str, config: &Config, hashmap: &mut HashMap<String, Item>, provenance: &mut Provenance) -> Option<Schema> {
+    let inline = hashmap.remove(SCHEMA_KEY);
+    let schema_path = vec![SCHEMA_KEY.to_string()];
+    if let Some(ref file_name) = config.schema_file_name {
+        return Some(load_file(program_name, config, file_name, provenance));
+    }
+    let schema = match inline.as_ref().map(unwrap) {
+        None => None,
+        Some(Item::Text(file_name)) => Some(load_file(program_name, config, file_name, provenance)),
+        Some(Item::Table(table)) => match Schema::keys(table, &schema_path, provenance) {
+            Ok(keys) => Some(Schema::root(keys)),
+            Err(e) => exit_with_schema_error(e),
+        },
+        Some(_) => exit_with_schema_error((locate(provenance, &schema_path), format!("`{}` must be a file name or a table", SCHEMA_KEY))),
+    };
+    provenance.forget(&schema_path);
+    schema
+}
@@ -0,0 +1,600 @@
+// TOML reader for `import`.
+//
+// Reads TOML 1.0 into smartconf items. Dates and times are kept as
+// strings; negative numbers, `inf` and `nan` are errors, as smartconf
+// numbers cannot hold them.
+
+use ::std::collections::HashMap;
+
+use crate::json::{Error, Locations};
+use crate::{Item, Number};
+
+type Table = HashMap<String, Item>;
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    line_number: usize,
+    column_number: usize,
+    root: Table,
+    // Key path of the current `[table]` and whether it is (inside) an
+    // element of an array of tables
+    table: Vec<String>,
+    in_array: bool,
+    // Headers of the `[table]`s defined so far
+    headers: Vec<Vec<String>>,
+    locations: Locations,
+}
+
+fn is_bare_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(offset, c)| self.chars.get(self.index + offset) == Some(&c))
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line_number += 1;
+            self.column_number = 1;
+        } else {
+            self.column_number += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, Error> {
+        Err((self.line_number, self.column_number, message.to_string()))
+    }
+
+    // Spaces and tabs
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t') = self.peek() {
+            self.next();
+        }
+    }
+
+    // Whitespace, newlines and comments, as between the items of an array
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.next();
+                },
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.next();
+                    }
+                },
+                _ => return,
+            }
+        }
+    }
+
+    // The rest of a line after a key-value pair or a header
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_whitespace();
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.next();
+            }
+        }
+        if self.peek() == Some('\r') {
+            self.next();
+        }
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.next();
+                Ok(())
+            },
+            Some(c) => self.error(&format!("Unexpected `{}` after the value", c)),
+        }
+    }
+
+    fn key(&mut self) -> Result<Vec<String>, Error> {
+        let mut path = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let key = match self.peek() {
+                Some('"') => {
+                    self.next();
+                    self.basic_string()?
+                },
+                Some('\'') => {
+                    self.next();
+                    self.literal_string()?
+                },
+                Some(c) if is_bare_key_char(c) => {
+                    let mut key = String::new();
+                    while let Some(c) = self.peek().filter(|c| is_bare_key_char(*c)) {
+                        key.push(c);
+                        self.next();
+                    }
+                    key
+                },
+                _ => return self.error("Expected a key"),
+            };
+            path.push(key);
+            self.skip_whitespace();
+            if self.peek() != Some('.') {
+                return Ok(path);
+            }
+            self.next();
+        }
+    }
+
+    // After the `\`; errors point at it
+    fn escape(&mut self) -> Result<char, Error> {
+        let (line_number, column_number) = (self.line_number, self.column_number - 1);
+        let invalid = |message: String| Err((line_number, column_number, message));
+        let c = match self.next() {
+            Some(c) => c,
+            None => return self.error("Unterminated string"),
+        };
+        let length = match c {
+            'b' => return Ok('\x08'),
+            't' => return Ok('\t'),
+            'n' => return Ok('\n'),
+            'f' => return Ok('\x0C'),
+            'r' => return Ok('\r'),
+            '"' => return Ok('"'),
+            '\\' => return Ok('\\'),
+            'u' => 4,
+            'U' => 8,
+            _ => return invalid(format!("Invalid escape: `\\{}`", c)),
+        };
+        let mut code = 0;
+        for _ in 0..length {
+            match self.next().and_then(|c| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return invalid(String::from("Invalid escape")),
+            }
+        }
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => invalid(String::from("Invalid escape")),
+        }
+    }
+
+    // After the opening `"`
+    fn basic_string(&mut self) -> Result<String, Error> {
+        let mut result = String::new();
+        loop {
+            if matches!(self.peek(), None | Some('\n')) {
+                return self.error("Unterminated string");
+            }
+            match self.next() {
+                Some('"') => return Ok(result),
+                Some('\\') => result.push(self.escape()?),
+                Some(c) => result.push(c),
+                None => unreachable!(),
+            }
+        }
+    }
+
+    // After the opening `'`
+    fn literal_string(&mut self) -> Result<String, Error> {
+        let mut result = String::new();
+        loop {
+            if matches!(self.peek(), None | Some('\n')) {
+                return self.error("Unterminated string");
+            }
+            match self.next() {
+                Some('\'') => return Ok(result),
+                Some(c) => result.push(c),
+                None => unreachable!(),
+            }
+        }
+    }
+
+    // After the opening `"""` or `'''`
+    fn multiline_string(&mut self, quote: char) -> Result<String, Error> {
+        // A newline right after the opening quotes is not part of the string
+        if self.starts_with("\r\n") {
+            self.next();
+        }
+        if self.peek() == Some('\n') {
+            self.next();
+        }
+        let mut result = String::new();
+        loop {
+            // The closing quotes can follow up to two quotes of the string
+            let quotes = self.chars[self.index..].iter().take_while(|c| **c == quote).count();
+            if quotes >= 3 {
+                if quotes > 5 {
+                    return self.error("Too many quotes");
+                }
+                for _ in 0..quotes - 3 {
+                    result.push(quote);
+                }
+                for _ in 0..quotes {
+                    self.next();
+                }
+                return Ok(result);
+            }
+            match self.next() {
+                None => return self.error("Unterminated string"),
+                Some('\\') if quote == '"' => {
+                    // A backslash at the end of a line trims the whitespace after it
+                    let rest = self.chars[self.index..].iter().take_while(|c| matches!(c, ' ' | '\t' | '\r')).count();
+                    if self.chars.get(self.index + rest) == Some(&'\n') {
+                        while let Some(' ' | '\t' | '\r' | '\n') = self.peek() {
+                            self.next();
+                        }
+                    } else {
+                        result.push(self.escape()?);
+                    }
+                },
+                Some(c) => result.push(c),
+            }
+        }
+    }
+
+    // A number, a boolean, a date or a time, up to the end of the value
+    fn scalar(&mut self) -> Result<Item, Error> {
+        let (line_number, column_number) = (self.line_number, self.column_number);
+        let mut text = String::new();
+        while let Some(c) = self.peek().filter(|c| !matches!(c, ',' | ']' | '}' | '#' | '\r' | '\n')) {
+            text.push(c);
+            self.next();
+        }
+        let text = text.trim_end();
+        let error = |message: String| Err((line_number, column_number, message));
+        match text {
+            "true" => return Ok(Item::Boolean(true)),
+            "false" => return Ok(Item::Boolean(false)),
+            "inf" | "+inf" | "-inf" | "nan" | "+nan" | "-nan" => {
+                return error(format!("`{}` is not supported", text));
+            },
+            "" => return error(String::from("Expected a value")),
+            _ => {},
+        }
+        if text.starts_with('-') {
+            return error(format!("Negative numbers are not supported: {}", text));
+        }
+        let number = text.strip_prefix('+').unwrap_or(text).replace('_', "");
+        for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+            if let Some(digits) = number.strip_prefix(prefix) {
+                return match usize::from_str_radix(digits, radix) {
+                    Ok(value) => Ok(Item::Number(Number::USIZE { value })),
+                    Err(_) => error(format!("Invalid number: {}", text)),
+                };
+            }
+        }
+        // `1979-05-27`, `07:32:00` and the like
+        let date = text.get(..4).is_some_and(|year| year.chars().all(|c| c.is_ascii_digit())) && text[4..].starts_with('-');
+        if date || text.contains(':') {
+            return Ok(Item::Text(text.to_string()));
+        }
+        if number.chars().all(|c| c.is_ascii_digit()) {
+            return match number.parse() {
+                Ok(value) => Ok(Item::Number(Number::USIZE { value })),
+                Err(_) => error(format!("Number is too large: {}", text)),
+            };
+        }
+        match number.parse::<f64>() {
+            Ok(_) if !number.starts_with(|c: char| c.is_ascii_digit()) => error(format!("Invalid value: `{}`", text)),
+            Ok(value) if value.is_finite() => Ok(Item::Number(Number::FLOAT { value })),
+            Ok(_) => error(format!("Number is too large: {}", text)),
+            Err(_) => error(format!("Invalid value: `{}`", text)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Item, Error> {
+        self.skip_whitespace();
+        if self.starts_with("\"\"\"") || self.starts_with("'''") {
+            let quote = self.next().unwrap();
+            self.next();
+            self.next();
+            return Ok(Item::Text(self.multiline_string(quote)?));
+        }
+        match self.peek() {
+            Some('"') => {
+                self.next();
+                Ok(Item::Text(self.basic_string()?))
+            },
+            Some('\'') => {
+                self.next();
+                Ok(Item::Text(self.literal_string()?))
+            },
+            Some('[') => {
+                self.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.peek() == Some(']') {
+                        self.next();
+                        return Ok(Item::List(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_blank();
+                    match self.peek() {
+                        Some(',') => {
+                            self.next();
+                        },
+                        Some(']') => {},
+                        _ => return self.error("Expected `,` or `]`"),
+                    }
+                }
+            },
+            Some('{') => {
+                self.next();
+                let mut table = Table::new();
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.next();
+                    return Ok(Item::Table(table));
+                }
+                loop {
+                    let key = self.key()?;
+                    if self.peek() != Some('=') {
+                        return self.error("Expected `=`");
+                    }
+                    self.next();
+                    let value = self.value()?;
+                    if let Err(message) = insert(&mut table, &key, value) {
+                        return self.error(&message);
+                    }
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(',') => {},
+                        Some('}') => {
+                            self.next();
+                            return Ok(Item::Table(table));
+                        },
+                        _ => return self.error("Expected `,` or `}`"),
+                    }
+                    self.next();
+                }
+            },
+            _ => self.scalar(),
+        }
+    }
+
+    // `[table]` or `[[array]]`, after the first `[`
+    fn header(&mut self) -> Result<(), Error> {
+        let array = self.peek() == Some('[');
+        if array {
+            self.next();
+        }
+        let (line_number, column_number) = (self.line_number, self.column_number);
+        let path = self.key()?;
+        let closing = if array { "]]" } else { "]" };
+        if !self.starts_with(closing) {
+            return self.error(&format!("Expected `{}`", closing));
+        }
+        for _ in 0..closing.len() {
+            self.next();
+        }
+        let (parents, key) = (&path[..path.len() - 1], &path[path.len() - 1]);
+        let in_array = match walk(&mut self.root, parents) {
+            Ok((table, in_array)) => {
+                if array {
+                    match table.entry(key.clone()).or_insert_with(|| Item::List(Vec::new())) {
+                        Item::List(list) => list.push(Item::Table(Table::new())),
+                        _ => return Err((line_number, column_number, format!("`{}` is not an array of tables", path.join(".")))),
+                    }
+                } else {
+                    match table.entry(key.clone()).or_insert_with(|| Item::Table(Table::new())) {
+                        Item::Table(_) => {},
+                        _ => return Err((line_number, column_number, format!("`{}` is not a table", path.join(".")))),
+                    }
+                }
+                in_array
+            },
+            Err(message) => return Err((line_number, column_number, message)),
+        };
+        if !array && !in_array {
+            if self.headers.contains(&path) {
+                return Err((line_number, column_number, format!("Table `{}` is defined twice", path.join("."))));
+            }
+            self.headers.push(path.clone());
+        }
+        if !in_array {
+            self.locations.push((path.clone(), line_number, column_number));
+        }
+        self.table = path;
+        self.in_array = in_array || array;
+        Ok(())
+    }
+
+    fn key_value(&mut self) -> Result<(), Error> {
+        let key = self.key()?;
+        if self.peek() != Some('=') {
+            return self.error("Expected `=`");
+        }
+        self.next();
+        self.skip_whitespace();
+        let (line_number, column_number) = (self.line_number, self.column_number);
+        let value = self.value()?;
+        let table = match walk(&mut self.root, &self.table) {
+            Ok((table, _)) => table,
+            Err(message) => return Err((line_number, column_number, message)),
+        };
+        if let Err(message) = insert(table, &key, value) {
+            return Err((line_number, column_number, message));
+        }
+        if !self.in_array {
+            let mut path = self.table.clone();
+            path.extend(key);
+            self.locations.push((path, line_number, column_number));
+        }
+        Ok(())
+    }
+}
+
+// Table at `path` in `table`, creating missing tables and going into
+// the last element of arrays of tables; also whether it is in one
+fn walk<'a>(mut table: &'a mut Table, path: &[String]) -> Result<(&'a mut Table, bool), String> {
+    let mut in_array = false;
+    for (index, key) in path.iter().enumerate() {
+        table = match table.entry(key.clone()).or_insert_with(|| Item::Table(Table::new())) {
+            Item::Table(table) => table,
+            Item::List(list) if matches!(list.last(), Some(Item::Table(_))) => {
+                in_array = true;
+                match list.last_mut() {
+                    Some(Item::Table(table)) => table,
+                    _ => unreachable!(),
+                }
+            },
+            _ => return Err(format!("`{}` is not a table", path[..=index].join("."))),
+        };
+    }
+    Ok((table, in_array))
+}
+
+// Defines the dotted key `path` of `table`
+fn insert(table: &mut Table, path: &[String], value: Item) -> Result<(), String> {
+    let (parents, key) = (&path[..path.len() - 1], &path[path.len() - 1]);
+    let (table, _) = walk(table, parents)?;
+    if table.contains_key(key) {
+        return Err(format!("`{}` is defined twice", path.join(".")));
+    }
+    table.insert(key.clone(), value);
+    Ok(())
+}
+
+pub fn parse(text: &str) -> Result<(Table, Locations), Error> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        index: 0,
+        line_number: 1,
+        column_number: 1,
+        root: Table::new(),
+        table: Vec::new(),
+        in_array: false,
+        headers: Vec::new(),
+        locations: Vec::new(),
+    };
+    loop {
+        parser.skip_blank();
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                parser.next();
+                parser.header()?;
+            },
+            Some(_) => parser.key_value()?,
+        }
+        parser.end_of_line()?;
+    }
+    Ok((parser.root, parser.locations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `text` read and written back as smartconf
+    fn read(text: &str) -> String {
+        let (table, _) = parse(text).unwrap();
+        crate::fmt::item(&Item::Table(table))
+    }
+
+    fn error(text: &str) -> Error {
+        parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn dotted_keys() {
+        assert_eq!(read("a.b.c = 1\na . d = 'x'\n\"q.k\".e = true\n"), "\
+a: {
+    b: {
+        c: 1
+    }
+    d: 'x'
+}
+'q.k': {
+    e: true
+}
+");
+        assert_eq!(read("[t]\nx.y = 1\n[t.z]\nw = 2\n"), "t: {\n    x: {\n        y: 1\n    }\n    z: {\n        w: 2\n    }\n}\n");
+        assert_eq!(error("a.b = 1\na.b.c = 2\n"), (2, 9, String::from("`a.b` is not a table")));
+        assert_eq!(error("a = 1\na = 2\n"), (2, 5, String::from("`a` is defined twice")));
+    }
+
+    #[test]
+    fn arrays_of_tables() {
+        let text = "\
+[[products]]
+name = 'Hammer'
+
+[[products]]
+
+[[products]]
+name = 'Nail'
+[products.dims]
+w = 2
+";
+        assert_eq!(read(text), "\
+products: [
+    {
+        name: 'Hammer'
+    },
+    {},
+    {
+        dims: {
+            w: 2
+        }
+        name: 'Nail'
+    },
+]
+");
+        assert_eq!(error("a = 1\n[[a]]\n"), (2, 3, String::from("`a` is not an array of tables")));
+        assert_eq!(error("[a]\n[a]\n"), (2, 2, String::from("Table `a` is defined twice")));
+    }
+
+    #[test]
+    fn multiline_strings() {
+        assert_eq!(read("s = \"\"\"\nRoses\\tare\nred\"\"\"\n"), "s: 'Roses\\tare\\nred'\n");
+        // A backslash at the end of a line joins it with the next
+        assert_eq!(read("s = \"\"\"\\\n    one \\\n    two\"\"\"\n"), "s: 'one two'\n");
+        assert_eq!(read("s = '''C:\\n\n'''\n"), "s: 'C:\\\\n\\n'\n");
+        assert_eq!(read("s = \"\"\"say \"hi\"\"\"\"\"\n"), "s: 'say \"hi\"\"'\n");
+        assert_eq!(error("s = \"\"\"abc\n"), (2, 1, String::from("Unterminated string")));
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(read("a = [1, 0x1f, 1_000, 2.5e1]\nb = {x = 'y', z = {}}\n"), "a: [1, 31, 1000, 25.0]\nb: {\n    x: 'y'\n    z: {}\n}\n");
+        assert_eq!(read("d = 1979-05-27T07:32:00Z\nt = 07:32:00\n"), "d: '1979-05-27T07:32:00Z'\nt: '07:32:00'\n");
+        assert_eq!(read("# comment\n\na = [\n  1, # one\n  2,\n]\n"), "a: [1, 2]\n");
+    }
+
+    #[test]
+    fn error_locations() {
+        assert_eq!(error("x = 'a' b\n"), (1, 9, String::from("Unexpected `b` after the value")));
+        assert_eq!(error("x = 1 2\n"), (1, 5, String::from("Invalid value: `1 2`")));
+        assert_eq!(error("x 1\n"), (1, 3, String::from("Expected `=`")));
+        assert_eq!(error("x = -1\n"), (1, 5, String::from("Negative numbers are not supported: -1")));
+        assert_eq!(error("x = inf\n"), (1, 5, String::from("`inf` is not supported")));
+        assert_eq!(error("x = \"a\\qb\"\n"), (1, 7, String::from("Invalid escape: `\\q`")));
+        assert_eq!(error("x = 'abc\n"), (1, 9, String::from("Unterminated string")));
+        assert_eq!(error("x = {a = 'y' b = 2}\n"), (1, 14, String::from("Expected `,` or `}`")));
+        assert_eq!(error("[a\n"), (1, 3, String::from("Expected `]`")));
+        assert_eq!(error("= 1\n"), (1, 1, String::from("Expected a key")));
+        assert_eq!(error("x = aé€\n"), (1, 5, String::from("Invalid value: `aé€`")));
+        assert_eq!(error("x = 2024é\n"), (1, 5, String::from("Invalid value: `2024é`")));
+    }
+
+    #[test]
+    fn locations() {
+        let (_, locations) = parse("a = 1\n[t]\n  b.c = 'x'\n[[l]]\nd = 2\n").unwrap();
+        assert_eq!(locations, vec![
+            (vec![String::from("a")], 1, 5),
+            (vec![String::from("t")], 2, 2),
+            (vec![String::from("t"), String::from("b"), String::from("c")], 3, 9),
+            // Not the keys inside an array of tables
+            (vec![String::from("l")], 4, 3),
+        ]);
+    }
+}
@@ -0,0 +1,85 @@
+// `--watch`: generate again whenever an input file or a file it
+// includes changes.
+//
+// Generating exits the process on the first error, so each build runs
+// in a child process with the same arguments, which lists the files it
+// reads with --list-files. Files are polled, which works everywhere;
+// a build starts once they have stopped changing for `DEBOUNCE`.
+
+use crate::Config;
+
+const POLL_INTERVAL: ::std::time::Duration = ::std::time::Duration::from_millis(100);
+const DEBOUNCE: ::std::time::Duration = ::std::time::Duration::from_millis(200);
+
+// Modification time of each file, None if it cannot be read
+fn snapshot(files: &[String]) -> Vec<Option<::std::time::SystemTime>> {
+    files.iter().map(|file_name| {
+        ::std::fs::metadata(file_name).and_then(|metadata| metadata.modified()).ok()
+    }).collect()
+}
+
+// Generates once; returns the files to watch
+fn build(config: &Config, list_files: &str) -> Vec<String> {
+    let args = ::std::env::args().skip(1).filter(|arg| arg != "--watch");
+    let status = ::std::env::current_exe().and_then(|exe| {
+        ::std::process::Command::new(exe)
+            .args(args)
+            .arg("--list-files")
+            .arg(list_files)
+            .status()
+    });
+    match status {
+        Ok(status) if status.success() => {
+            eprintln!("watch: Generated");
+        },
+        Ok(status) => match status.code() {
+            Some(code) => eprintln!("watch: Failed with code {}", code),
+            None => eprintln!("watch: Failed"),
+        },
+        Err(e) => {
+            error!("watch: Cannot run {}: {}", ::std::env::args().next().unwrap_or_default(), e);
+            ::std::process::exit(1);
+        },
+    }
+    let mut files = config.input_file_names.clone();
+    if let Ok(list) = ::std::fs::read_to_string(list_files) {
+        for file_name in list.lines() {
+            if !files.iter().any(|watched| watched == file_name) {
+                files.push(file_name.to_string());
+            }
+        }
+    }
+    files
+}
+
+pub fn run(config: Config) -> ! {
+    let list_files = match config.list_files {
+        Some(ref list_files) => list_files.clone(),
+        None => ::std::env::temp_dir()
+            .join(format!("smartconf.{}.files", ::std::process::id()))
+            .to_string_lossy()
+            .to_string(),
+    };
+    loop {
+        let files = build(&config, &list_files);
+        let mut last = snapshot(&files);
+        eprintln!("watch: Waiting for changes in {} files", files.len());
+        loop {
+            ::std::thread::sleep(POLL_INTERVAL);
+            let current = snapshot(&files);
+            if current != last {
+                last = current;
+                break;
+            }
+        }
+        // Wait for the editor (or `git checkout`) to finish writing
+        loop {
+            ::std::thread::sleep(DEBOUNCE);
+            let current = snapshot(&files);
+            if current == last {
+                break;
+            }
+            last = current;
+        }
+    }
+}
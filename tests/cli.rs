@@ -0,0 +1,200 @@
+// End-to-end tests: each runs the smartconf binary on files in its own
+// scratch directory and checks what it writes back.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("smartconf-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_smartconf"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// Stdout of a run that must succeed
+fn ok(dir: &Path, args: &[&str]) -> String {
+    let output = run(dir, args);
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn set_new_keys_stay_parseable() {
+    let dir = scratch("set");
+    fs::write(dir.join("s.smf"), "x: 1\n").unwrap();
+    ok(&dir, &["set", "s.smf", "y1.z2", "3"]);
+    ok(&dir, &["set", "s.smf", "'a-1'", "4"]);
+    ok(&dir, &["set", "s.smf", "'b\\c'", "5"]);
+    ok(&dir, &["set", "s.smf", "'true'", "6"]);
+    ok(&dir, &["--format", "json", "s.smf"]);
+    assert_eq!(ok(&dir, &["get", "s.smf", "y1.z2"]), "3\n");
+    assert_eq!(ok(&dir, &["get", "s.smf", "'a-1'"]), "4\n");
+    assert_eq!(ok(&dir, &["get", "s.smf", "'b\\c'"]), "5\n");
+    assert_eq!(ok(&dir, &["get", "s.smf", "'true'"]), "6\n");
+    assert_eq!(ok(&dir, &["get", "s.smf", "x"]), "1\n");
+}
+
+#[test]
+fn fmt_keeps_quotes_keys_need() {
+    let dir = scratch("fmt");
+    let source = "'a1': 1\n'a-1': 2\nb: {\n    'c2': 3\n}\n'd1'.'e2': 4\n'f'.g: 5\n";
+    fs::write(dir.join("f.smf"), source).unwrap();
+    let before = ok(&dir, &["--format", "smartconf", "f.smf"]);
+    ok(&dir, &["fmt", "f.smf"]);
+    assert_eq!(fs::read_to_string(dir.join("f.smf")).unwrap(), "'a1': 1\n'a-1': 2\nb: {\n    'c2': 3\n}\n'd1'.'e2': 4\nf.g: 5\n");
+    assert_eq!(ok(&dir, &["--format", "smartconf", "f.smf"]), before);
+    ok(&dir, &["fmt", "--check", "f.smf"]);
+}
+
+#[test]
+fn smartconf_format_reads_back() {
+    let dir = scratch("convert");
+    let json = r#"{"a1": 1, "a-1": {"b2": [1, {"c3": true}]}, "true": "${x}", "q": "it's \"q\"\n", "f": 2.0, "": {}}"#;
+    fs::write(dir.join("a.json"), json).unwrap();
+    let converted = ok(&dir, &["--from", "json", "--format", "smartconf", "a.json"]);
+    fs::write(dir.join("a.smf"), &converted).unwrap();
+    assert_eq!(ok(&dir, &["--format", "smartconf", "a.smf"]), converted);
+    assert_eq!(ok(&dir, &["get", "a.smf", "'a-1'.'b2'"]), ok(&dir, &["get", "a.json", "'a-1'.'b2'"]));
+    assert_eq!(ok(&dir, &["get", "a.smf", "'true'"]), "${x}\n");
+    assert_eq!(ok(&dir, &["get", "a.smf", "f"]), "2.0\n");
+    fs::write(dir.join("b.json"), ok(&dir, &["--format", "json", "a.smf"])).unwrap();
+    assert_eq!(ok(&dir, &["--format", "smartconf", "b.json"]), converted);
+}
+
+#[test]
+fn non_finite_floats_are_errors() {
+    let dir = scratch("floats");
+    fs::write(dir.join("a.json"), r#"{"y": 1, "x": 1e400}"#).unwrap();
+    fs::write(dir.join("a.toml"), "y = 1\nx = 1e400\n").unwrap();
+    fs::write(dir.join("a.smf"), format!("x: {}.0\n", "9".repeat(400))).unwrap();
+    for (file_name, location) in [("a.json", "a.json: 1: 15:"), ("a.toml", "a.toml: 2: 5:"), ("a.smf", "a.smf: ")] {
+        let output = run(&dir, &["--format", "json", file_name]);
+        assert_eq!(output.status.code(), Some(3));
+        assert!(String::from_utf8_lossy(&output.stderr).contains(location));
+        assert!(output.stdout.is_empty());
+    }
+}
+
+#[test]
+fn sh_format_sources_back() {
+    let dir = scratch("sh");
+    fs::write(dir.join("a.smf"), "server: {\n    name: \"it's $x\\n`id`\"\n    ports: [80, 443]\n}\n").unwrap();
+    fs::write(dir.join("out.sh"), ok(&dir, &["--format", "sh", "--uppercase", "a.smf"])).unwrap();
+    let output = Command::new("sh")
+        .current_dir(&dir)
+        .arg("-c")
+        .arg(". ./out.sh; printf '%s|%s' \"$SERVER_NAME\" \"$SERVER_PORTS_1\"")
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "it's $x\n`id`|443");
+}
+
+#[test]
+fn huge_pattern_repetitions_are_schema_errors() {
+    let dir = scratch("repetition");
+    fs::write(dir.join("a.smf"), "name: 'a'\n").unwrap();
+    for pattern in ["^(a?){200000}$", "((a{1000}){1000}){1000}"] {
+        fs::write(dir.join("schema.smf"), format!("name: {{ pattern: '{}' }}\n", pattern)).unwrap();
+        let output = run(&dir, &["validate", "--schema", "schema.smf", "a.smf"]);
+        assert_eq!(output.status.code(), Some(3));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid pattern: Repetition too large"));
+    }
+}
+
+#[test]
+fn from_does_not_apply_to_the_schema() {
+    let dir = scratch("from-schema");
+    fs::write(dir.join("schema.smf"), "name: { type: 'string' required: true }\n").unwrap();
+    fs::write(dir.join("config.txt"), r#"{"name": "a"}"#).unwrap();
+    ok(&dir, &["validate", "--from", "json", "--schema", "schema.smf", "config.txt"]);
+    fs::write(dir.join("config.txt"), r#"{"name": 1}"#).unwrap();
+    let output = run(&dir, &["validate", "--from", "json", "--schema", "schema.smf", "config.txt"]);
+    assert_eq!(output.status.code(), Some(6));
+}
+
+#[test]
+fn schema_includes_are_dependencies() {
+    let dir = scratch("schema-deps");
+    fs::write(dir.join("part.smf"), "name: { type: 'string' }\n").unwrap();
+    fs::write(dir.join("schema.smf"), "include 'part.smf'\n").unwrap();
+    fs::write(dir.join("a.smf"), "name: 'a'\n").unwrap();
+    ok(&dir, &["--schema", "schema.smf", "-M", "out.d", "--list-files", "list.txt", "-o", "out.json", "a.smf"]);
+    assert_eq!(fs::read_to_string(dir.join("list.txt")).unwrap(), "a.smf\nschema.smf\npart.smf\n");
+    let rule = fs::read_to_string(dir.join("out.d")).unwrap();
+    assert!(rule.starts_with("out.json: \\\n    a.smf \\\n    schema.smf \\\n    part.smf\n"), "{}", rule);
+}
+
+#[test]
+fn missing_keys_point_at_the_first_file() {
+    let dir = scratch("missing");
+    fs::write(dir.join("schema.smf"), "name: { required: true }\nt: { type: 'table' keys: { q: { required: true } } }\n").unwrap();
+    fs::write(dir.join("a.smf"), "\n\nt.z: 1\n").unwrap();
+    fs::write(dir.join("b.smf"), "t.y: 2\n").unwrap();
+    let output = run(&dir, &["validate", "--schema", "schema.smf", "a.smf", "b.smf"]);
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error: validator: Missing required key `name`"), "{}", stderr);
+    assert!(stderr.contains("a.smf: 3: 6: Missing required key `t.q`"), "{}", stderr);
+}
+
+#[test]
+fn set_after_removal_appends() {
+    let dir = scratch("set-removed");
+    for (source, key, expected) in [
+        ("a: 1\nunset a\n", "a", "a: 1\nunset a\na: 9\n"),
+        ("t.a: 1\nt: !delete\n", "t.a", "t.a: 1\nt: !delete\nt.a: 9\n"),
+        ("t: {\n    a: 1\n    unset a\n}\n", "t.a", "t: {\n    a: 1\n    unset a\n}\nt.a: 9\n"),
+        ("a: 1\nunset b\n", "a", "a: 9\nunset b\n"),
+        ("a: 1\na: !delete\n", "a", "a: 1\na: 9\n"),
+    ] {
+        fs::write(dir.join("s.smf"), source).unwrap();
+        ok(&dir, &["set", "s.smf", key, "9"]);
+        assert_eq!(fs::read_to_string(dir.join("s.smf")).unwrap(), expected);
+        assert_eq!(ok(&dir, &["get", "s.smf", key]), "9\n");
+    }
+}
+
+#[test]
+fn lsp_finds_keys_in_documents_with_errors() {
+    use std::io::Write;
+    use std::process::Stdio;
+    let dir = scratch("lsp");
+    let uri = format!("file://{}/a.smf", dir.display());
+    let text = "name: 'a'\\nport: ${missing}\\n";
+    let messages = [
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#.to_string(),
+        format!(r#"{{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {{"textDocument": {{"uri": "{}", "text": "{}"}}}}}}"#, uri, text),
+        format!(r#"{{"jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": {{"textDocument": {{"uri": "{}"}}, "position": {{"line": 0, "character": 1}}}}}}"#, uri),
+        format!(r#"{{"jsonrpc": "2.0", "id": 3, "method": "textDocument/definition", "params": {{"textDocument": {{"uri": "{}"}}, "position": {{"line": 1, "character": 2}}}}}}"#, uri),
+        r#"{"jsonrpc": "2.0", "id": 4, "method": "shutdown"}"#.to_string(),
+        r#"{"jsonrpc": "2.0", "method": "exit"}"#.to_string(),
+    ];
+    let mut child = Command::new(env!("CARGO_BIN_EXE_smartconf"))
+        .current_dir(&dir)
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for message in &messages {
+        write!(stdin, "Content-Length: {}\r\n\r\n{}", message.len(), message).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("publishDiagnostics"), "{}", stdout);
+    assert!(stdout.contains(&format!(r#"'a'\n```\nDefined at {}/a.smf: 1: 1"#, dir.display())), "{}", stdout);
+    assert!(stdout.contains(&format!(r#""uri":"{}","range":{{"start":{{"line":1,"character":0}}"#, uri)), "{}", stdout);
+}